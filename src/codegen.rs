@@ -1,32 +1,130 @@
-use crate::{expr::*, jit_ctx::JITContext, stmt::*};
+use crate::{
+    expr::*,
+    host,
+    module_ctx::{ModuleContext, VariableData},
+    stmt::*,
+};
 use codegen::ir::UserFuncName;
 use cranelift::prelude::*;
 use cranelift_jit::JITModule;
-use cranelift_module::{DataDescription, DataId, FuncId, Linkage, Module};
-use std::collections::HashMap;
+use cranelift_module::{DataDescription, DataId, FuncId, Linkage, Module, ModuleError};
+use cranelift_object::ObjectModule;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
 
-pub struct Codegen<'a> {
-    jit: &'a mut JITContext,
+// ModuleError は大きいため、ボックス化して Result を小さく保つ
+pub type CodegenResult<T> = Result<T, Box<ModuleError>>;
+
+pub struct Codegen<'a, M: Module> {
+    jit: &'a mut ModuleContext<M>,
 }
 
-impl<'a> Codegen<'a> {
-    pub fn new(jit: &'a mut JITContext) -> Self {
+impl<'a, M: Module> Codegen<'a, M> {
+    pub fn new(jit: &'a mut ModuleContext<M>) -> Self {
         Self { jit }
     }
+}
 
-    pub fn codegen(&mut self, stmt: &Statement) -> extern "C" fn() {
-        let func_id = self.codegen_stmt(stmt);
+impl Codegen<'_, JITModule> {
+    pub fn codegen(&mut self, stmt: &Statement) -> CodegenResult<extern "C" fn()> {
+        let func_id = self.codegen_stmt(stmt)?;
         self.jit.get_finalized_function(func_id)
     }
 
-    pub fn codegen_stmt(&mut self, stmt: &Statement) -> FuncId {
-        let func_name = format!("stmt{}", self.jit.stmt_index);
+    // 式をコンパイルし、その値を返す関数を得る
+    pub fn codegen_eval(&mut self, expr: &Expression) -> CodegenResult<extern "C" fn() -> u32> {
+        let func_id = self.codegen_expr(expr)?;
+        self.jit.get_finalized_eval_function(func_id)
+    }
+
+    // すべての文をコンパイルしてから、まとめてリンクする
+    pub fn codegen_program(&mut self, stmts: &[Statement]) -> CodegenResult<Vec<extern "C" fn()>> {
+        let func_ids = stmts
+            .iter()
+            .map(|stmt| self.codegen_stmt(stmt))
+            .collect::<CodegenResult<Vec<FuncId>>>()?;
+        func_ids
+            .into_iter()
+            .map(|func_id| self.jit.get_finalized_function(func_id))
+            .collect()
+    }
+}
+
+impl Codegen<'_, ObjectModule> {
+    // 各文の関数を順に呼び出し、0 を返す `main` 関数を定義する
+    pub fn codegen_main(&mut self, func_ids: &[FuncId]) -> CodegenResult<FuncId> {
+        let mut func_sig = self.jit.module.make_signature();
+        func_sig.returns.push(AbiParam::new(types::I32));
+        let main_id = self
+            .jit
+            .module
+            .declare_function("main", Linkage::Export, &func_sig)?;
+
+        self.jit.ctx.func.signature = func_sig;
+        self.jit.ctx.func.name = UserFuncName::user(0, main_id.as_u32());
+
+        let mut func_builder = FunctionBuilder::new(&mut self.jit.ctx.func, &mut self.jit.func_ctx);
+        let block = func_builder.create_block();
+        func_builder.switch_to_block(block);
+        for func_id in func_ids {
+            let local_func = self
+                .jit
+                .module
+                .declare_func_in_func(*func_id, func_builder.func);
+            func_builder.ins().call(local_func, &[]);
+        }
+        let exit_code = func_builder.ins().iconst(types::I32, 0);
+        func_builder.ins().return_(&[exit_code]);
+        func_builder.seal_all_blocks();
+        func_builder.finalize();
+
+        self.jit
+            .module
+            .define_function(main_id, &mut self.jit.ctx)?;
+        self.jit.module.clear_context(&mut self.jit.ctx);
+        Ok(main_id)
+    }
+}
+
+impl<M: Module> Codegen<'_, M> {
+    pub fn codegen_stmt(&mut self, stmt: &Statement) -> CodegenResult<FuncId> {
         let func_sig = self.jit.module.make_signature();
+        self.define_function("stmt", func_sig, stmt, |stmt_codegen| {
+            stmt_codegen.codegen_stmt(stmt)?;
+            stmt_codegen.func_builder.ins().return_(&[]);
+            Ok(())
+        })
+    }
+
+    // 式の値を返す関数を定義する。真偽値は 0 か 1 として返す
+    pub fn codegen_expr(&mut self, expr: &Expression) -> CodegenResult<FuncId> {
+        let mut func_sig = self.jit.module.make_signature();
+        func_sig.returns.push(AbiParam::new(types::I32));
+        self.define_function("expr", func_sig, expr, |stmt_codegen| {
+            stmt_codegen.declare_calls(expr)?;
+            let value = stmt_codegen.codegen_expr(expr);
+            let value = stmt_codegen.extend_to_i32(value);
+            stmt_codegen.func_builder.ins().return_(&[value]);
+            Ok(())
+        })
+    }
+
+    // `{prefix}N` という名前の関数を定義し、本体を body で生成する
+    // source は IR などを表示する際に、関数の元になったコードとして添える
+    fn define_function(
+        &mut self,
+        prefix: &str,
+        func_sig: Signature,
+        source: &dyn Display,
+        body: impl FnOnce(&mut StmtCodegen<'_, M>) -> CodegenResult<()>,
+    ) -> CodegenResult<FuncId> {
+        let func_name = format!("{prefix}{}", self.jit.stmt_index);
         let func_id = self
             .jit
             .module
-            .declare_function(&func_name, Linkage::Local, &func_sig)
-            .unwrap();
+            .declare_function(&func_name, Linkage::Local, &func_sig)?;
 
         self.jit.ctx.func.signature = func_sig;
         self.jit.ctx.func.name = UserFuncName::user(0, func_id.as_u32());
@@ -40,107 +138,200 @@ impl<'a> Codegen<'a> {
             data_description: &mut self.jit.data_description,
             variables: &mut self.jit.variables,
             print_func: self.jit.print_func,
+            host_functions: &mut self.jit.host_functions,
+            bool_functions: &self.jit.bool_functions,
         };
 
         let block = stmt_codegen.func_builder.create_block();
         stmt_codegen.func_builder.switch_to_block(block);
 
-        stmt_codegen.codegen_stmt(stmt);
+        let result = body(&mut stmt_codegen);
+        if let Err(err) = result {
+            // 途中まで生成した関数を捨て、次の関数の生成に影響しないようにする
+            self.jit.module.clear_context(&mut self.jit.ctx);
+            return Err(err);
+        }
 
-        stmt_codegen.func_builder.ins().return_(&[]);
         stmt_codegen.func_builder.seal_all_blocks();
         stmt_codegen.func_builder.finalize();
 
+        if self.jit.emit.clif {
+            let text = format!("; {func_name}: {source}\n{}", self.jit.ctx.func.display());
+            self.jit.write_emitted(text);
+        }
+
+        self.jit.ctx.set_disasm(self.jit.emit.asm);
         self.jit
             .module
             .define_function(func_id, &mut self.jit.ctx)
-            .unwrap();
+            .inspect_err(|_| self.jit.module.clear_context(&mut self.jit.ctx))?;
+
+        // コンパイル後の ctx.func には最適化パスを通した IR が残っている
+        if self.jit.emit.clif_opt {
+            let text = format!(
+                "; {func_name} (optimized): {source}\n{}",
+                self.jit.ctx.func.display()
+            );
+            self.jit.write_emitted(text);
+        }
+        if let Some(disasm) = self
+            .jit
+            .ctx
+            .compiled_code()
+            .and_then(|code| code.vcode.clone())
+        {
+            self.jit
+                .write_emitted(format!("; {func_name} (asm): {source}\n{disasm}"));
+        }
         self.jit.module.clear_context(&mut self.jit.ctx);
 
         self.jit.stmt_index += 1;
-        func_id
+        Ok(func_id)
     }
 }
 
-struct StmtCodegen<'a> {
-    module: &'a mut JITModule,
+struct StmtCodegen<'a, M: Module> {
+    module: &'a mut M,
     func_builder: FunctionBuilder<'a>,
     data_description: &'a mut DataDescription,
-    variables: &'a mut HashMap<String, DataId>,
+    variables: &'a mut HashMap<String, VariableData>,
     print_func: FuncId,
+    host_functions: &'a mut HashMap<String, FuncId>,
+    bool_functions: &'a HashSet<String>,
 }
 
-impl<'a> StmtCodegen<'a> {
-    pub fn codegen_stmt(&mut self, stmt: &Statement) {
+impl<M: Module> StmtCodegen<'_, M> {
+    pub fn codegen_stmt(&mut self, stmt: &Statement) -> CodegenResult<()> {
         match stmt {
-            Statement::Print(expr) => self.codegen_print(expr),
+            Statement::Print(expr) => {
+                self.declare_calls(expr)?;
+                self.codegen_print(expr);
+            }
             Statement::Assign(ident, expr) => {
-                let ident_str: &str = &*ident;
+                self.declare_calls(expr)?;
+                let ident_str: &str = ident;
                 if self.variables.contains_key(ident_str) {
                     self.codegen_assign(ident, expr);
                 } else {
-                    self.codegen_def_var(ident, expr);
+                    self.codegen_def_var(ident, expr)?;
+                }
+            }
+            Statement::If(cond, then) => {
+                self.declare_calls(cond)?;
+                self.codegen_if(cond, then)?;
+            }
+            Statement::Eval(expr) => {
+                self.declare_calls(expr)?;
+                self.codegen_expr(expr);
+            }
+        }
+        Ok(())
+    }
+
+    // 式の中で呼び出すホストの関数を、まだ宣言していなければインポートとして宣言する
+    // 引数と戻り値はどれも I32 で受け渡す
+    fn declare_calls(&mut self, expr: &Expression) -> CodegenResult<()> {
+        match expr {
+            Expression::Identifier(_) | Expression::Number(_) => {}
+            Expression::Add(lhs, rhs) | Expression::Sub(lhs, rhs) | Expression::Comp(lhs, rhs) => {
+                self.declare_calls(lhs)?;
+                self.declare_calls(rhs)?;
+            }
+            Expression::Call(func_name, args) => {
+                let func_name: &str = func_name;
+                if !self.host_functions.contains_key(func_name) {
+                    let mut func_sig = self.module.make_signature();
+                    func_sig
+                        .params
+                        .extend(args.iter().map(|_| AbiParam::new(types::I32)));
+                    func_sig.returns.push(AbiParam::new(types::I32));
+                    let func_id = self.module.declare_function(
+                        &host::symbol_name(func_name),
+                        Linkage::Import,
+                        &func_sig,
+                    )?;
+                    self.host_functions.insert(func_name.to_owned(), func_id);
+                }
+                for arg in args {
+                    self.declare_calls(arg)?;
                 }
             }
-            Statement::If(cond, then) => self.codegen_if(cond, then),
         }
+        Ok(())
     }
 
     fn codegen_print(&mut self, expr: &Expression) {
         let local_func = self
             .module
-            .declare_func_in_func(self.print_func, &mut self.func_builder.func);
+            .declare_func_in_func(self.print_func, self.func_builder.func);
         let arg = self.codegen_expr(expr);
         self.func_builder.ins().call(local_func, &[arg]);
     }
 
     fn codegen_assign(&mut self, ident: &Identifier, expr: &Expression) {
-        let ident: &str = &*ident;
-        let global_ref = {
-            let data = *self.variables.get(ident).unwrap();
-            let var = self
-                .module
-                .declare_data_in_func(data, &mut self.func_builder.func);
-            self.func_builder
-                .ins()
-                .global_value(self.module.target_config().pointer_type(), var)
-        };
+        let ident: &str = ident;
+        let var = self.variables[ident];
+        let global_ref = self.data_addr(var.value);
         let value = self.codegen_expr(expr);
+        // 真偽値も 4 バイトの変数に 0 か 1 として格納する
+        let value = self.extend_to_i32(value);
         self.func_builder
             .ins()
             .store(MemFlags::new(), value, global_ref, 0);
+        let stored_ref = self.data_addr(var.stored);
+        let one = self.func_builder.ins().iconst(types::I8, 1);
+        self.func_builder
+            .ins()
+            .store(MemFlags::new(), one, stored_ref, 0);
     }
 
-    fn codegen_def_var(&mut self, ident: &Identifier, expr: &Expression) {
-        let ident_str: &str = &*ident;
-        let data = self
+    fn data_addr(&mut self, data: DataId) -> Value {
+        let global_value = self
             .module
-            .declare_data(ident_str, Linkage::Local, true, false)
-            .unwrap();
-        match expr {
-            // NOTE: 整数値は以下のようにも初期化できるが、簡単のため処理を other の節に統一した
-            // Expression::Number(num) => {
-            //     let num: u32 = num.into();
-            //     self.data_description.define(Box::new(num.to_ne_bytes()));
-            //     self.module
-            //         .define_data(data, self.data_description)
-            //         .unwrap();
-            //     self.variables.insert(ident_str.to_owned(), data);
-            // }
-            Expression::Comp(_, _) => unreachable!(),
-            other => {
-                self.data_description.define_zeroinit(4);
-                self.module
-                    .define_data(data, self.data_description)
-                    .unwrap();
-                self.variables.insert(ident_str.to_owned(), data);
-                self.codegen_assign(ident, other);
-            }
+            .declare_data_in_func(data, self.func_builder.func);
+        self.func_builder
+            .ins()
+            .global_value(self.module.target_config().pointer_type(), global_value)
+    }
+
+    // 真偽値（比較の結果は I8）を I32 の 0 か 1 に拡張する
+    fn extend_to_i32(&mut self, value: Value) -> Value {
+        if self.func_builder.func.dfg.value_type(value) == types::I32 {
+            value
+        } else {
+            self.func_builder.ins().uextend(types::I32, value)
         }
+    }
+
+    fn codegen_def_var(&mut self, ident: &Identifier, expr: &Expression) -> CodegenResult<()> {
+        let ident_str: &str = ident;
+        // NOTE: 整数値は以下のようにも初期化できるが、簡単のため常にゼロ初期化してから代入する
+        // if let Expression::Number(num) = expr {
+        //     let num: u32 = num.into();
+        //     self.data_description.define(Box::new(num.to_ne_bytes()));
+        //     ...
+        // }
+        // 識別子に含まれない `.` を付けて、`main` や `stmt0` などの関数名との衝突を避ける
+        let value = self.define_zeroinit_data(&format!("var.{ident_str}"), 4)?;
+        let stored = self.define_zeroinit_data(&format!("stored.{ident_str}"), 1)?;
+        self.variables
+            .insert(ident_str.to_owned(), VariableData { value, stored });
+        self.codegen_assign(ident, expr);
+        Ok(())
+    }
+
+    fn define_zeroinit_data(&mut self, name: &str, size: usize) -> CodegenResult<DataId> {
+        let data = self
+            .module
+            .declare_data(name, Linkage::Local, true, false)?;
+        self.data_description.define_zeroinit(size);
+        let result = self.module.define_data(data, self.data_description);
         self.data_description.clear();
+        result?;
+        Ok(data)
     }
 
-    fn codegen_if(&mut self, cond: &Expression, then: &Statement) {
+    fn codegen_if(&mut self, cond: &Expression, then: &Statement) -> CodegenResult<()> {
         let cond_val = self.codegen_expr(cond);
 
         let then_block = self.func_builder.create_block();
@@ -153,7 +344,7 @@ impl<'a> StmtCodegen<'a> {
 
         self.func_builder.switch_to_block(then_block);
         self.func_builder.seal_block(then_block);
-        self.codegen_stmt(then);
+        self.codegen_stmt(then)?;
         self.func_builder.ins().jump(merge_block, &[]);
 
         self.func_builder.switch_to_block(else_block);
@@ -162,6 +353,7 @@ impl<'a> StmtCodegen<'a> {
 
         self.func_builder.switch_to_block(merge_block);
         self.func_builder.seal_block(merge_block);
+        Ok(())
     }
 
     fn codegen_expr(&mut self, expr: &Expression) -> Value {
@@ -186,20 +378,34 @@ impl<'a> StmtCodegen<'a> {
                 self.func_builder.ins().icmp(IntCC::Equal, lhs, rhs)
             }
             Expression::Identifier(ident) => {
-                let ident: &str = &*ident;
-                let global_ref = {
-                    let data = *self.variables.get(ident).unwrap();
-                    let global_var = self
-                        .module
-                        .declare_data_in_func(data, &mut self.func_builder.func);
-                    self.func_builder
-                        .ins()
-                        .global_value(self.module.target_config().pointer_type(), global_var)
-                };
+                let ident: &str = ident;
+                let global_ref = self.data_addr(self.variables[ident].value);
                 self.func_builder
                     .ins()
                     .load(types::I32, MemFlags::new(), global_ref, 0)
             }
+            // 関数は declare_calls で宣言済み。真偽値の引数も I32 に拡張して渡す
+            Expression::Call(func_name, args) => {
+                let func_name: &str = func_name;
+                let local_func = self
+                    .module
+                    .declare_func_in_func(self.host_functions[func_name], self.func_builder.func);
+                let args: Vec<Value> = args
+                    .iter()
+                    .map(|arg| {
+                        let value = self.codegen_expr(arg);
+                        self.extend_to_i32(value)
+                    })
+                    .collect();
+                let call = self.func_builder.ins().call(local_func, &args);
+                let ret = self.func_builder.inst_results(call)[0];
+                // ホストの関数が 0 か 1 以外を返しても、真偽値は 0 か 1 として扱う
+                if self.bool_functions.contains(func_name) {
+                    self.func_builder.ins().icmp_imm(IntCC::NotEqual, ret, 0)
+                } else {
+                    ret
+                }
+            }
         }
     }
 }
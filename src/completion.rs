@@ -0,0 +1,134 @@
+use crate::lexer::{self, TokenKind};
+
+// REPL の入力のうち、カーソル位置 pos の直前の単語を補完する
+// 補完する単語の開始位置と、候補を名前の順に返す
+// 文の先頭ではキーワードと変数名、式の途中では変数名、`if` の条件の後では `then` を候補にする
+pub fn complete(line: &str, pos: usize, names: &[String]) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start = before
+        .char_indices()
+        .rfind(|(_, c)| !c.is_ascii_alphanumeric())
+        .map_or(0, |(index, c)| index + c.len_utf8());
+    let prefix = &before[start..];
+    // 数字で始まる単語は識別子ではない
+    if prefix.starts_with(|c: char| c.is_ascii_digit()) {
+        return (start, Vec::new());
+    }
+    let Ok(tokens) = lexer::tokenize(&before[..start]) else {
+        return (start, Vec::new());
+    };
+    let kinds: Vec<TokenKind> = tokens
+        .into_iter()
+        .map(|token| token.kind)
+        .filter(|kind| !matches!(kind, TokenKind::Newline | TokenKind::Eof))
+        .collect();
+
+    let names = names.iter().map(String::as_str);
+    let mut candidates: Vec<&str> = match kinds.last() {
+        // 文の先頭
+        None | Some(TokenKind::Then) => ["if", "print"].into_iter().chain(names).collect(),
+        // 式の先頭
+        Some(
+            TokenKind::If
+            | TokenKind::Print
+            | TokenKind::Assign
+            | TokenKind::Plus
+            | TokenKind::Minus
+            | TokenKind::EqEq
+            | TokenKind::LParen
+            | TokenKind::Comma,
+        ) => names.collect(),
+        // 式の後
+        Some(TokenKind::Identifier(_) | TokenKind::Number(_) | TokenKind::RParen)
+            if kinds.contains(&TokenKind::If) && !kinds.contains(&TokenKind::Then) =>
+        {
+            vec!["then"]
+        }
+        _ => Vec::new(),
+    };
+    candidates.retain(|candidate| candidate.starts_with(prefix));
+    candidates.sort_unstable();
+    candidates.dedup();
+    (start, candidates.into_iter().map(str::to_owned).collect())
+}
+
+// `:` で始まる REPL のコマンド名を補完する
+// カーソルが `:` より前にあるか、コマンド名の後に空白がある場合は補完しない
+pub fn complete_command(line: &str, pos: usize, commands: &[&str]) -> (usize, Vec<String>) {
+    match line[..pos].strip_prefix(':') {
+        Some(prefix) if !prefix.contains(char::is_whitespace) => {
+            let candidates = commands
+                .iter()
+                .filter(|name| name.starts_with(prefix))
+                .map(|name| format!(":{name}"))
+                .collect();
+            (0, candidates)
+        }
+        _ => (pos, Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{complete, complete_command};
+
+    fn candidates(line: &str) -> (usize, Vec<String>) {
+        let names = ["apple", "answer", "banana", "iffy"].map(str::to_owned);
+        complete(line, line.len(), &names)
+    }
+
+    #[test]
+    fn complete_statement_start_test() {
+        assert_eq!(
+            candidates("a"),
+            (0, vec!["answer".to_owned(), "apple".to_owned()])
+        );
+        assert_eq!(
+            candidates("i"),
+            (0, vec!["if".to_owned(), "iffy".to_owned()])
+        );
+        assert_eq!(
+            candidates("if apple == 1 then p"),
+            (19, vec!["print".to_owned()])
+        );
+    }
+
+    #[test]
+    fn complete_expression_test() {
+        assert_eq!(candidates("print b"), (6, vec!["banana".to_owned()]));
+        assert_eq!(candidates("x = (apple+an"), (11, vec!["answer".to_owned()]));
+        assert_eq!(candidates("print i"), (6, vec!["iffy".to_owned()]));
+        assert_eq!(candidates("if apple == 1 t"), (14, vec!["then".to_owned()]));
+        assert_eq!(candidates("print f(1, b"), (11, vec!["banana".to_owned()]));
+    }
+
+    #[test]
+    fn complete_nothing_test() {
+        assert_eq!(candidates("print 1"), (6, vec![]));
+        assert_eq!(candidates("x = apple t"), (10, vec![]));
+        assert_eq!(candidates("print 1 * a"), (10, vec![]));
+        assert_eq!(candidates("# a"), (2, vec![]));
+    }
+
+    #[test]
+    fn complete_non_ascii_test() {
+        assert_eq!(candidates("print é"), (8, vec![]));
+        assert_eq!(
+            candidates("# é\nprint a"),
+            (11, vec!["answer".to_owned(), "apple".to_owned()])
+        );
+        assert_eq!(candidates("# ąb"), (4, vec![]));
+    }
+
+    #[test]
+    fn complete_command_test() {
+        let commands = ["undo", "type", "vars"];
+        assert_eq!(
+            complete_command(":u", 2, &commands),
+            (0, vec![":undo".to_owned()])
+        );
+        assert_eq!(complete_command(":undo", 0, &commands), (0, vec![]));
+        assert_eq!(complete_command(":type a", 7, &commands), (7, vec![]));
+        assert_eq!(complete_command(":", 1, &commands).1.len(), 3);
+    }
+}
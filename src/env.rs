@@ -1,5 +1,7 @@
 use crate::{
     expr::{Expression, Identifier},
+    host::HostFunction,
+    init::{self, Assigned},
     stmt::Statement,
     type_::{Type, TypeRepr},
 };
@@ -8,59 +10,253 @@ use std::{
     fmt::{self, Display, Formatter},
 };
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Usage {
+    IfCondition,
+    Print,
+    Operand(&'static str),
+    // 関数名と、1 始まりの引数の位置
+    Argument(String, usize),
+}
+
+impl Display for Usage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IfCondition => write!(f, "in `if` condition"),
+            Self::Print => write!(f, "in `print` statement"),
+            Self::Operand(op) => write!(f, "as an operand of `{op}`"),
+            Self::Argument(func_name, index) => write!(f, "as argument {index} of `{func_name}`"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
-    TypeError(TypeRepr, TypeRepr),
+    TypeError {
+        expr: Expression,
+        found: TypeRepr,
+        expected: TypeRepr,
+        usage: Usage,
+    },
+    AssignTypeError {
+        var_name: String,
+        var_type: TypeRepr,
+        expr: Expression,
+        expr_type: TypeRepr,
+    },
     VarNameError(String),
+    FuncNameError(String),
+    ArgCountError {
+        func_name: String,
+        expected: usize,
+        found: usize,
+    },
+    InitError(init::Error),
+}
+
+// エラーメッセージ中で式を指し示すための表現
+struct Subject<'a>(&'a Expression);
+
+impl Display for Subject<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Expression::Identifier(ident) => write!(f, "variable `{ident}`"),
+            expr => write!(f, "`{expr}`"),
+        }
+    }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            Self::TypeError(left, right) => {
-                write!(f, "Type error: not same types (`{left:?}` and `{right:?}`)",)
-            }
+            Self::TypeError {
+                expr,
+                found,
+                expected,
+                usage,
+            } => write!(
+                f,
+                "Type error: {} has type {found} but is used as {expected} {usage}",
+                Subject(expr)
+            ),
+            Self::AssignTypeError {
+                var_name,
+                var_type,
+                expr,
+                expr_type,
+            } => write!(
+                f,
+                "Type error: variable `{var_name}` has type {var_type} but is assigned {} of type {expr_type}",
+                Subject(expr)
+            ),
             Self::VarNameError(name) => write!(f, "Name error: variable `{name}` not found"),
+            Self::FuncNameError(name) => write!(f, "Name error: function `{name}` not found"),
+            Self::ArgCountError {
+                func_name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Type error: function `{func_name}` takes {expected} argument(s) but {found} were given"
+            ),
+            Self::InitError(err) => write!(f, "{err}"),
         }
     }
 }
 
+// 単一化に失敗した左右の（解決済みの）型表現
+#[derive(Debug)]
+struct Mismatch(TypeRepr, TypeRepr);
+
+// 検査中の文で変更した環境の記録。検査に失敗したら、これをもとに検査前の状態に戻す
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct UndoLog {
+    // 新しく追加した変数の名前
+    names: Vec<String>,
+    // 型を代入した型変数
+    bindings: Vec<usize>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Env(Vec<Type>, HashMap<String, TypeRepr>);
+pub struct Env(
+    Vec<Type>,
+    HashMap<String, TypeRepr>,
+    Assigned,
+    // 呼び出せる関数の、引数と戻り値の型
+    HashMap<String, (Vec<TypeRepr>, TypeRepr)>,
+    UndoLog,
+);
 
 impl Env {
+    // table に含まれる変数は代入済みとして扱う
     pub fn new(types: Vec<Type>, table: HashMap<String, TypeRepr>) -> Self {
-        Self(types, table)
+        let assigned = Assigned::new(table.keys().cloned());
+        Self(types, table, assigned, HashMap::new(), UndoLog::default())
     }
 
     pub fn new_empty() -> Self {
         Self::new(Vec::new(), HashMap::new())
     }
 
+    // 変数の名前、型、代入済みであることが確定しているかの組から環境を作る
+    pub fn from_variables<I: IntoIterator<Item = (String, TypeRepr, bool)>>(vars: I) -> Self {
+        let mut table = HashMap::new();
+        let mut definite = Vec::new();
+        let mut maybe = Vec::new();
+        for (name, ty, assigned) in vars {
+            if assigned {
+                definite.push(name.clone());
+            } else {
+                maybe.push(name.clone());
+            }
+            table.insert(name, ty);
+        }
+        Self(
+            Vec::new(),
+            table,
+            Assigned::with_maybe(definite, maybe),
+            HashMap::new(),
+            UndoLog::default(),
+        )
+    }
+
+    // ホストの関数を呼び出せるようにする。同じ名前の関数があれば置き換える
+    pub fn add_function(&mut self, func: &HostFunction) {
+        self.3
+            .insert(func.name.clone(), (func.params.clone(), func.ret));
+    }
+
+    // 変数が代入済みであることが確定しているか
+    pub fn is_assigned(&self, name: &str) -> bool {
+        self.2.is_assigned(name)
+    }
+
+    // 文の検査に失敗した場合は、検査前の状態に戻す
     pub fn analyze_stmt(&mut self, stmt: &Statement) -> Result<(), Error> {
+        self.transaction(|env| {
+            env.check_stmt(stmt)?;
+            env.2.analyze_stmt(stmt).map_err(Error::InitError)
+        })
+    }
+
+    // f が失敗した場合は、f で変更した変数と型変数を元に戻す
+    // 環境全体を複製しないため、検査にかかる時間は環境の大きさによらない
+    fn transaction<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let types_len = self.0.len();
+        self.4 = UndoLog::default();
+        let result = f(self);
+        let log = std::mem::take(&mut self.4);
+        if result.is_err() {
+            for name in log.names {
+                self.1.remove(&name);
+            }
+            // 型を代入できるのは、それまで型が未定だった型変数だけ
+            for id in log.bindings.into_iter().filter(|id| *id < types_len) {
+                self.0[id] = Type::Var(id, None);
+            }
+            self.0.truncate(types_len);
+        }
+        result
+    }
+
+    fn check_stmt(&mut self, stmt: &Statement) -> Result<(), Error> {
         match stmt {
             Statement::Assign(ident, expr) => {
                 let ident_type = self.add_var(ident);
-                let expr_type = self.analyze_expr(&expr)?;
-                self.unify(&ident_type, &expr_type)?;
+                let expr_type = self.check_expr(expr)?;
+                self.unify(&ident_type, &expr_type)
+                    .map_err(|Mismatch(var_type, expr_type)| Error::AssignTypeError {
+                        var_name: ident.to_string(),
+                        var_type,
+                        expr: expr.clone(),
+                        expr_type,
+                    })?;
             }
             Statement::If(cond, body_stmt) => {
-                let cond_type = self.analyze_expr(&cond)?;
-                self.unify(&cond_type, &TypeRepr::Bool)?;
-                self.analyze_stmt(&body_stmt)?;
+                self.expect_type(cond, TypeRepr::Bool, Usage::IfCondition)?;
+                self.check_stmt(body_stmt)?;
             }
             Statement::Print(expr) => {
-                let expr_type = self.analyze_expr(&expr)?;
-                self.unify(&expr_type, &TypeRepr::Int)?;
+                self.expect_type(expr, TypeRepr::Int, Usage::Print)?;
+            }
+            Statement::Eval(expr) => {
+                self.check_expr(expr)?;
             }
         }
         Ok(())
     }
 
-    pub(self) fn analyze_expr(&mut self, expr: &Expression) -> Result<TypeRepr, Error> {
+    // 式の型を解決して返す。検査に失敗した場合は、検査前の状態に戻す
+    pub fn analyze_expr(&mut self, expr: &Expression) -> Result<TypeRepr, Error> {
+        let ty = self.transaction(|env| {
+            let ty = env.check_expr(expr)?;
+            env.2.analyze_expr(expr).map_err(Error::InitError)?;
+            Ok(ty)
+        })?;
+        Ok(self.resolve(&ty))
+    }
+
+    // 定義済みの変数とその型を、名前の順に返す
+    pub fn variables(&mut self) -> Vec<(String, TypeRepr)> {
+        let mut names: Vec<String> = self.1.keys().cloned().collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| {
+                let ty = self.1[&name];
+                let ty = self.resolve(&ty);
+                (name, ty)
+            })
+            .collect()
+    }
+
+    fn check_expr(&mut self, expr: &Expression) -> Result<TypeRepr, Error> {
         match expr {
             Expression::Identifier(ident) => {
-                let ident: &String = &*ident;
+                let ident: &String = ident;
                 if let Some(type_repr) = self.1.get(ident) {
                     Ok(*type_repr)
                 } else {
@@ -68,25 +264,60 @@ impl Env {
                 }
             }
             Expression::Add(left, right) | Expression::Sub(left, right) => {
-                let left_type = self.analyze_expr(&left)?;
-                self.unify(&left_type, &TypeRepr::Int)?;
-                let right_type = self.analyze_expr(&right)?;
-                self.unify(&right_type, &TypeRepr::Int)?;
+                let op = if matches!(expr, Expression::Add(_, _)) {
+                    "+"
+                } else {
+                    "-"
+                };
+                self.expect_type(left, TypeRepr::Int, Usage::Operand(op))?;
+                self.expect_type(right, TypeRepr::Int, Usage::Operand(op))?;
                 Ok(TypeRepr::Int)
             }
             Expression::Comp(left, right) => {
-                let left_type = self.analyze_expr(&left)?;
-                self.unify(&left_type, &TypeRepr::Int)?;
-                let right_type = self.analyze_expr(&right)?;
-                self.unify(&right_type, &TypeRepr::Int)?;
+                self.expect_type(left, TypeRepr::Int, Usage::Operand("=="))?;
+                self.expect_type(right, TypeRepr::Int, Usage::Operand("=="))?;
                 Ok(TypeRepr::Bool)
             }
             Expression::Number(_) => Ok(TypeRepr::Int),
+            Expression::Call(func_name, args) => {
+                let func_name: &String = func_name;
+                let Some((params, ret)) = self.3.get(func_name).cloned() else {
+                    return Err(Error::FuncNameError(func_name.clone()));
+                };
+                if args.len() != params.len() {
+                    return Err(Error::ArgCountError {
+                        func_name: func_name.clone(),
+                        expected: params.len(),
+                        found: args.len(),
+                    });
+                }
+                for (index, (arg, param)) in args.iter().zip(params).enumerate() {
+                    self.expect_type(arg, param, Usage::Argument(func_name.clone(), index + 1))?;
+                }
+                Ok(ret)
+            }
         }
     }
 
+    // 式の型を解析し、期待する型と単一化する
+    fn expect_type(
+        &mut self,
+        expr: &Expression,
+        expected: TypeRepr,
+        usage: Usage,
+    ) -> Result<(), Error> {
+        let expr_type = self.check_expr(expr)?;
+        self.unify(&expr_type, &expected)
+            .map_err(|Mismatch(found, expected)| Error::TypeError {
+                expr: expr.clone(),
+                found,
+                expected,
+                usage,
+            })
+    }
+
     fn add_var(&mut self, var_name: &Identifier) -> TypeRepr {
-        let var_name: &String = &*var_name;
+        let var_name: &String = var_name;
         if let Some(var_type) = self.1.get(var_name) {
             *var_type
         } else {
@@ -94,11 +325,12 @@ impl Env {
             let type_repr = TypeRepr::Id(types_size);
             self.1.insert(var_name.clone(), type_repr);
             self.0.push(Type::Var(types_size, None));
+            self.4.names.push(var_name.clone());
             type_repr
         }
     }
 
-    pub(self) fn unify(&mut self, left: &TypeRepr, right: &TypeRepr) -> Result<(), Error> {
+    pub(self) fn unify(&mut self, left: &TypeRepr, right: &TypeRepr) -> Result<(), Mismatch> {
         let left_resolved = self.resolve(left);
         let right_resolved = self.resolve(right);
 
@@ -108,7 +340,8 @@ impl Env {
                 // 右の型表現が左と等しくない型変数、あるいは具体的な型である時に、左の型変数に型代入する
                 if left_resolved != right_resolved {
                     if let Type::Var(id, _) = self.0.get(left_id).unwrap() {
-                        *self.0.get_mut(left_id).unwrap() = Type::Var(*id, Some(right_resolved))
+                        *self.0.get_mut(left_id).unwrap() = Type::Var(*id, Some(right_resolved));
+                        self.4.bindings.push(left_id);
                     }
                 }
                 Ok(())
@@ -121,7 +354,7 @@ impl Env {
                 if left_resolved == right_resolved {
                     Ok(())
                 } else {
-                    Err(Error::TypeError(left_resolved, right_resolved))
+                    Err(Mismatch(left_resolved, right_resolved))
                 }
             }
         }
@@ -144,7 +377,13 @@ impl Env {
 #[cfg(test)]
 mod tests {
     use super::Env;
-    use crate::{env::Error, expr::*, stmt::*, type_::TypeRepr};
+    use crate::{
+        env::Error,
+        expr::*,
+        host::{HostFn, HostFunction},
+        stmt::*,
+        type_::TypeRepr,
+    };
 
     #[test]
     fn type_equality_test() {
@@ -275,9 +514,221 @@ mod tests {
             Box::new(Statement::Print(Expression::Number(3u32.into()))),
         );
 
+        let err = env.analyze_stmt(&if_stmt).unwrap_err();
+        assert!(matches!(err, Error::TypeError { .. }));
+        assert_eq!(
+            err.to_string(),
+            "Type error: `2` has type int but is used as bool in `if` condition"
+        );
+    }
+
+    #[test]
+    fn type_error_message_test() {
+        let mut env = Env::new_empty();
+
+        let ident_a: Identifier = "a".to_owned().into();
+        add_var(&mut env, ident_a.clone(), Expression::Number(1u32.into()));
+
+        let if_stmt = Statement::If(
+            Expression::Identifier(ident_a.clone()),
+            Box::new(Statement::Print(Expression::Number(3u32.into()))),
+        );
+        assert_eq!(
+            env.analyze_stmt(&if_stmt).unwrap_err().to_string(),
+            "Type error: variable `a` has type int but is used as bool in `if` condition"
+        );
+
+        let comp = Expression::Comp(
+            Box::new(Expression::Identifier(ident_a.clone())),
+            Box::new(Expression::Add(
+                Box::new(Expression::Number(1u32.into())),
+                Box::new(Expression::Number(2u32.into())),
+            )),
+        );
+        let print_stmt = Statement::Print(comp.clone());
+        assert_eq!(
+            env.analyze_stmt(&print_stmt).unwrap_err().to_string(),
+            "Type error: `a == 1 + 2` has type bool but is used as int in `print` statement"
+        );
+
+        let assign_stmt = Statement::Assign(ident_a, comp);
+        assert_eq!(
+            env.analyze_stmt(&assign_stmt).unwrap_err().to_string(),
+            "Type error: variable `a` has type int but is assigned `a == 1 + 2` of type bool"
+        );
+    }
+
+    #[test]
+    fn resolved_type_error_message_test() {
+        let mut env = Env::new_empty();
+
+        // b の型は型変数を経由して bool に解決される
+        let ident_b: Identifier = "b".to_owned().into();
+        let assign_stmt = Statement::Assign(
+            ident_b.clone(),
+            Expression::Comp(
+                Box::new(Expression::Number(1u32.into())),
+                Box::new(Expression::Number(1u32.into())),
+            ),
+        );
+        assert!(env.analyze_stmt(&assign_stmt).is_ok());
+
+        let print_stmt = Statement::Print(Expression::Add(
+            Box::new(Expression::Number(1u32.into())),
+            Box::new(Expression::Identifier(ident_b)),
+        ));
+        assert_eq!(
+            env.analyze_stmt(&print_stmt).unwrap_err().to_string(),
+            "Type error: variable `b` has type bool but is used as int as an operand of `+`"
+        );
+    }
+
+    #[test]
+    fn failed_stmt_rollback_test() {
+        let mut env = Env::new_empty();
+
+        let ident_a: Identifier = "a".to_owned().into();
+        add_var(&mut env, ident_a.clone(), Expression::Number(1u32.into()));
+        let before = env.clone();
+
+        // x は登録されず、a の型も変わらない
+        let assign_stmt = Statement::Assign(
+            "x".to_owned().into(),
+            Expression::Add(
+                Box::new(Expression::Identifier(ident_a.clone())),
+                Box::new(Expression::Identifier("undefined".to_owned().into())),
+            ),
+        );
+        assert!(matches!(
+            env.analyze_stmt(&assign_stmt),
+            Err(Error::VarNameError(_))
+        ));
+        assert_eq!(env, before);
+
+        let if_stmt = Statement::If(
+            Expression::Comp(
+                Box::new(Expression::Identifier(ident_a)),
+                Box::new(Expression::Number(1u32.into())),
+            ),
+            Box::new(Statement::Assign(
+                "y".to_owned().into(),
+                Expression::Comp(
+                    Box::new(Expression::Number(1u32.into())),
+                    Box::new(Expression::Identifier("x".to_owned().into())),
+                ),
+            )),
+        );
+        assert!(env.analyze_stmt(&if_stmt).is_err());
+        assert_eq!(env, before);
         assert!(matches!(
-            env.analyze_stmt(&if_stmt),
-            Err(Error::TypeError(_, _))
+            env.analyze_expr(&Expression::Identifier("x".to_owned().into())),
+            Err(Error::VarNameError(_))
         ));
     }
+
+    #[test]
+    fn variables_test() {
+        let mut env = Env::new_empty();
+        for source in ["b = 1", "a = b == 2", "if a then c = b"] {
+            let stmt = crate::parser::parse(source).unwrap();
+            env.analyze_stmt(&stmt).unwrap();
+        }
+        assert_eq!(
+            env.variables(),
+            vec![
+                ("a".to_owned(), TypeRepr::Bool),
+                ("b".to_owned(), TypeRepr::Int),
+                ("c".to_owned(), TypeRepr::Int),
+            ]
+        );
+
+        // c は代入されていない可能性があるため、式の検査は失敗し、状態も変わらない
+        let before = env.clone();
+        let expr = Expression::Add(
+            Box::new(Expression::Identifier("b".to_owned().into())),
+            Box::new(Expression::Identifier("c".to_owned().into())),
+        );
+        assert!(matches!(env.analyze_expr(&expr), Err(Error::InitError(_))));
+        assert_eq!(env, before);
+    }
+
+    #[test]
+    fn from_variables_test() {
+        let mut env = Env::from_variables([
+            ("a".to_owned(), TypeRepr::Int, true),
+            ("b".to_owned(), TypeRepr::Bool, false),
+        ]);
+        assert!(env.is_assigned("a"));
+        assert!(!env.is_assigned("b"));
+        assert_eq!(
+            env.variables(),
+            vec![
+                ("a".to_owned(), TypeRepr::Int),
+                ("b".to_owned(), TypeRepr::Bool),
+            ]
+        );
+        for (source, ok) in [("c = a + 1", true), ("print b", false), ("b = a", false)] {
+            let stmt = crate::parser::parse(source).unwrap();
+            assert_eq!(env.analyze_stmt(&stmt).is_ok(), ok, "{source}");
+        }
+    }
+
+    #[test]
+    fn unassigned_var_test() {
+        let mut env = Env::new_empty();
+
+        let assign_stmt = Statement::Assign(
+            "a".to_owned().into(),
+            Expression::Add(
+                Box::new(Expression::Identifier("a".to_owned().into())),
+                Box::new(Expression::Number(1u32.into())),
+            ),
+        );
+        let err = env.analyze_stmt(&assign_stmt).unwrap_err();
+        assert!(matches!(err, Error::InitError(_)));
+        assert_eq!(
+            err.to_string(),
+            "Init error: variable `a` is read before it is assigned"
+        );
+        assert_eq!(env, Env::new_empty());
+    }
+
+    extern "C" fn is_zero(n: u32) -> u32 {
+        (n == 0) as u32
+    }
+
+    #[test]
+    fn call_type_test() {
+        let mut env = Env::new_empty();
+        let func = HostFunction::new(
+            "isZero",
+            &[TypeRepr::Int],
+            TypeRepr::Bool,
+            HostFn::Arity1(is_zero),
+        )
+        .unwrap();
+        env.add_function(&func);
+        let expr = crate::parser::parse_expr("isZero(1 + 2)").unwrap();
+        assert_eq!(env.analyze_expr(&expr).unwrap(), TypeRepr::Bool);
+
+        for (source, message) in [
+            (
+                "isZero(1 == 2)",
+                "Type error: `1 == 2` has type bool but is used as int as argument 1 of `isZero`",
+            ),
+            (
+                "isZero()",
+                "Type error: function `isZero` takes 1 argument(s) but 0 were given",
+            ),
+            (
+                "isZero(1) + 1",
+                "Type error: `isZero(1)` has type bool but is used as int as an operand of `+`",
+            ),
+            ("isOne(1)", "Name error: function `isOne` not found"),
+            ("isZero(x)", "Name error: variable `x` not found"),
+        ] {
+            let expr = crate::parser::parse_expr(source).unwrap();
+            assert_eq!(env.analyze_expr(&expr).unwrap_err().to_string(), message);
+        }
+    }
 }
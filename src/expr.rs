@@ -1,4 +1,7 @@
-use std::ops::Deref;
+use std::{
+    fmt::{self, Display, Formatter},
+    ops::Deref,
+};
 
 #[derive(Debug, Clone, PartialEq, Hash, Eq)]
 pub struct Identifier(String);
@@ -9,6 +12,12 @@ impl From<String> for Identifier {
     }
 }
 
+impl Display for Identifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 impl Deref for Identifier {
     type Target = String;
 
@@ -20,6 +29,12 @@ impl Deref for Identifier {
 #[derive(Debug, Clone, Copy, PartialEq, Hash, Eq)]
 pub struct Number(u32);
 
+impl Display for Number {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 impl From<u32> for Number {
     fn from(value: u32) -> Self {
         Number(value)
@@ -45,4 +60,94 @@ pub enum Expression {
     Comp(Box<Expression>, Box<Expression>),
     Add(Box<Expression>, Box<Expression>),
     Sub(Box<Expression>, Box<Expression>),
+    // ホストの関数の呼び出し
+    Call(Identifier, Vec<Expression>),
+}
+
+impl Expression {
+    // 結合の強さ。値が大きいほど強く結合する
+    fn precedence(&self) -> u8 {
+        match self {
+            Self::Comp(_, _) => 1,
+            Self::Add(_, _) | Self::Sub(_, _) => 2,
+            Self::Identifier(_) | Self::Number(_) | Self::Call(_, _) => 3,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut Formatter<'_>, min_precedence: u8) -> fmt::Result {
+        if self.precedence() < min_precedence {
+            write!(f, "({self})")
+        } else {
+            write!(f, "{self}")
+        }
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Identifier(ident) => write!(f, "{ident}"),
+            Self::Number(num) => write!(f, "{num}"),
+            // `==` は結合しないため、左右どちらの比較式も括弧で囲む
+            Self::Comp(lhs, rhs) => {
+                lhs.fmt_operand(f, 2)?;
+                write!(f, " == ")?;
+                rhs.fmt_operand(f, 2)
+            }
+            // `+` と `-` は左結合のため、右辺の加減算は括弧で囲む
+            Self::Add(lhs, rhs) | Self::Sub(lhs, rhs) => {
+                let op = if matches!(self, Self::Add(_, _)) {
+                    '+'
+                } else {
+                    '-'
+                };
+                lhs.fmt_operand(f, 2)?;
+                write!(f, " {op} ")?;
+                rhs.fmt_operand(f, 3)
+            }
+            Self::Call(name, args) => {
+                write!(f, "{name}(")?;
+                for (index, arg) in args.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{arg}")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn num(n: u32) -> Box<Expression> {
+        Box::new(Expression::Number(n.into()))
+    }
+
+    #[test]
+    fn display_expression_test() {
+        let expr = Expression::Sub(Box::new(Expression::Add(num(1), num(2))), num(3));
+        assert_eq!(expr.to_string(), "1 + 2 - 3");
+
+        let expr = Expression::Add(num(1), Box::new(Expression::Sub(num(2), num(3))));
+        assert_eq!(expr.to_string(), "1 + (2 - 3)");
+
+        let expr = Expression::Comp(
+            Box::new(Expression::Identifier("a".to_owned().into())),
+            Box::new(Expression::Add(num(1), num(2))),
+        );
+        assert_eq!(expr.to_string(), "a == 1 + 2");
+
+        let expr = Expression::Comp(Box::new(expr), num(1));
+        assert_eq!(expr.to_string(), "(a == 1 + 2) == 1");
+
+        let expr = Expression::Call(
+            "f".to_owned().into(),
+            vec![*num(1), Expression::Call("g".to_owned().into(), vec![])],
+        );
+        assert_eq!(expr.to_string(), "f(1, g())");
+    }
 }
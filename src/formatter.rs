@@ -0,0 +1,58 @@
+use crate::parser::{self, Line};
+
+// ソースコードを標準的なスタイルに整形する
+// - 文は最小限の括弧で出力し、演算子の前後に空白を一つ置く
+// - 行頭と行末の空白を取り除き、改行は LF に揃える
+// - 連続する空行は一つにまとめ、ファイル先頭と末尾の空行は取り除く
+// - コメントは保持し、文の後ろのコメントは空白二つを空けて置く
+pub fn format_source(s: &str) -> Result<String, parser::Error> {
+    let mut output = String::new();
+    let mut pending_blank = false;
+    for line in parser::parse_lines(s)? {
+        let text = match line {
+            Line::Blank => {
+                pending_blank = !output.is_empty();
+                continue;
+            }
+            Line::Comment(comment) => comment,
+            Line::Statement(stmt, None) => stmt.to_string(),
+            Line::Statement(stmt, Some(comment)) => format!("{stmt}  {comment}"),
+        };
+        if pending_blank {
+            output.push('\n');
+            pending_blank = false;
+        }
+        output.push_str(&text);
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_source;
+
+    #[test]
+    fn format_source_test() {
+        let source =
+            "\n# header   \r\na=1+(2-3)\r\n\n\n\tif (a)==((1)) then   print a+(b+c) # trailing\n\n";
+        let expected =
+            "# header\na = 1 + (2 - 3)\n\nif a == 1 then print a + (b + c)  # trailing\n";
+        assert_eq!(format_source(source).unwrap(), expected);
+
+        // 整形済みのソースコードは変化しない
+        assert_eq!(format_source(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn format_nested_comment_test() {
+        let source = "a = (1 + # one\n  2) # two\nif a == 3 then # three\n  print a\n";
+        let expected = "a = 1 + 2  # one # two\nif a == 3 then print a  # three\n";
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn format_invalid_source_test() {
+        assert!(format_source("print\n").is_err());
+    }
+}
@@ -0,0 +1,386 @@
+// JIT とインタプリタの出力を比べる差分ファジング
+// 型の正しいプログラムをランダムに生成して両方で実行し、出力が食い違えばプログラムを縮小して報告する
+use crate::{
+    codegen::Codegen, env::Env, expr::Expression, interp::Interpreter, jit_ctx::JITContext,
+    module_ctx::OptLevel, optimize, stmt::Statement,
+};
+use std::{
+    cell::RefCell,
+    env,
+    fmt::Write,
+    panic::{self, AssertUnwindSafe},
+};
+
+// 外部クレートに依存せず、シードから同じプログラムを再現するための乱数生成器（xorshift64*）
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ty {
+    Int,
+    Bool,
+}
+
+struct Generator {
+    rng: Rng,
+    // 型が決まった変数。`if` の本体でだけ代入された変数も含む
+    vars: Vec<(String, Ty)>,
+    // 代入済みであることが確定していて、読み出してよい変数
+    readable: Vec<(String, Ty)>,
+}
+
+impl Generator {
+    fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed),
+            vars: Vec::new(),
+            readable: Vec::new(),
+        }
+    }
+
+    fn program(&mut self, len: usize) -> Vec<Statement> {
+        (0..len).map(|_| self.stmt(0)).collect()
+    }
+
+    // depth は `if` の入れ子の深さ
+    fn stmt(&mut self, depth: usize) -> Statement {
+        match self.rng.below(if depth < 2 { 4 } else { 3 }) {
+            0 => Statement::Print(self.int_expr(3)),
+            1 | 2 => self.assign(depth > 0),
+            _ => {
+                let cond = self.bool_expr(2);
+                Statement::If(cond, Box::new(self.stmt(depth + 1)))
+            }
+        }
+    }
+
+    fn assign(&mut self, in_if: bool) -> Statement {
+        let (name, ty) = if !self.vars.is_empty() && self.rng.chance(50) {
+            self.vars[self.rng.below(self.vars.len())].clone()
+        } else {
+            let ty = if self.rng.chance(70) {
+                Ty::Int
+            } else {
+                Ty::Bool
+            };
+            (format!("v{}", self.vars.len()), ty)
+        };
+        // 右辺を先に作るため、新しい変数が自身の右辺で読まれることはない
+        let expr = match ty {
+            Ty::Int => self.int_expr(3),
+            Ty::Bool => self.bool_expr(2),
+        };
+        if !self.vars.iter().any(|(var, _)| *var == name) {
+            self.vars.push((name.clone(), ty));
+        }
+        if !in_if && !self.readable.iter().any(|(var, _)| *var == name) {
+            self.readable.push((name.clone(), ty));
+        }
+        Statement::Assign(name.into(), expr)
+    }
+
+    fn readable_var(&mut self, ty: Ty) -> Option<Expression> {
+        let vars: Vec<&String> = self
+            .readable
+            .iter()
+            .filter(|(_, var_ty)| *var_ty == ty)
+            .map(|(name, _)| name)
+            .collect();
+        if vars.is_empty() {
+            return None;
+        }
+        let name = vars[self.rng.below(vars.len())].clone();
+        Some(Expression::Identifier(name.into()))
+    }
+
+    fn int_expr(&mut self, depth: usize) -> Expression {
+        if depth == 0 || self.rng.chance(30) {
+            if self.rng.chance(50) {
+                if let Some(var) = self.readable_var(Ty::Int) {
+                    return var;
+                }
+            }
+            return Expression::Number(self.number().into());
+        }
+        let left = Box::new(self.int_expr(depth - 1));
+        let right = Box::new(self.int_expr(depth - 1));
+        if self.rng.chance(50) {
+            Expression::Add(left, right)
+        } else {
+            Expression::Sub(left, right)
+        }
+    }
+
+    fn bool_expr(&mut self, depth: usize) -> Expression {
+        if self.rng.chance(30) {
+            if let Some(var) = self.readable_var(Ty::Bool) {
+                return var;
+            }
+        }
+        Expression::Comp(
+            Box::new(self.int_expr(depth)),
+            Box::new(self.int_expr(depth)),
+        )
+    }
+
+    // 折り返しが起きやすいように、境界付近の値を多めに選ぶ
+    fn number(&mut self) -> u32 {
+        const EDGES: [u32; 6] = [0, 1, 2, u32::MAX, u32::MAX - 1, 1 << 31];
+        if self.rng.chance(40) {
+            EDGES[self.rng.below(EDGES.len())]
+        } else {
+            self.rng.below(100) as u32
+        }
+    }
+}
+
+fn well_typed(stmts: &[Statement]) -> bool {
+    let mut env = Env::new_empty();
+    stmts.iter().all(|stmt| env.analyze_stmt(stmt).is_ok())
+}
+
+fn format_program(stmts: &[Statement]) -> String {
+    stmts.iter().map(|stmt| format!("{stmt}\n")).collect()
+}
+
+thread_local! {
+    static JIT_OUTPUT: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+extern "C" fn capture_print(n: u32) {
+    JIT_OUTPUT.with(|output| writeln!(output.borrow_mut(), "{n}").unwrap());
+}
+
+fn run_interp(stmts: &[Statement]) -> String {
+    let mut interp = Interpreter::with_output(Vec::new());
+    for stmt in stmts {
+        interp.exec_stmt(stmt).unwrap();
+    }
+    String::from_utf8(interp.output().clone()).unwrap()
+}
+
+// main と同じく最適化してから JIT で実行する。incremental では REPL のように一文ずつコンパイルして実行する
+fn run_jit(stmts: &[Statement], opt_level: OptLevel, incremental: bool) -> Result<String, String> {
+    JIT_OUTPUT.with(|output| output.borrow_mut().clear());
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut jit_ctx = JITContext::builder()
+            .opt_level(opt_level)
+            .print_func(capture_print)
+            .build();
+        let mut warnings = Vec::new();
+        let stmts: Vec<Statement> = stmts
+            .iter()
+            .filter_map(|stmt| optimize::optimize_stmt(stmt, &mut warnings))
+            .collect();
+        let mut codegen = Codegen::new(&mut jit_ctx);
+        if incremental {
+            for stmt in &stmts {
+                codegen.codegen(stmt).unwrap()();
+            }
+        } else {
+            for func_ptr in codegen.codegen_program(&stmts).unwrap() {
+                func_ptr();
+            }
+        }
+    }))
+    .map_err(|err| {
+        let message = err
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .or_else(|| err.downcast_ref::<&str>().copied())
+            .unwrap_or("unknown panic");
+        format!("JIT panicked: {message}")
+    })?;
+    Ok(JIT_OUTPUT.with(|output| output.borrow().clone()))
+}
+
+// 食い違いがあれば、その内容を返す
+fn check(stmts: &[Statement]) -> Option<String> {
+    let expected = run_interp(stmts);
+    for opt_level in [OptLevel::None, OptLevel::Speed] {
+        for incremental in [false, true] {
+            let actual = run_jit(stmts, opt_level, incremental);
+            if actual.as_ref() != Ok(&expected) {
+                return Some(format!(
+                    "opt_level: {opt_level:?}, incremental: {incremental}\n\
+                     interpreter output:\n{expected}JIT output:\n{}",
+                    actual.unwrap_or_else(|err| err + "\n")
+                ));
+            }
+        }
+    }
+    None
+}
+
+// 型が正しく、fails を満たしたままのプログラムに縮小する
+fn minimize(mut stmts: Vec<Statement>, fails: impl Fn(&[Statement]) -> bool) -> Vec<Statement> {
+    let accepts = |stmts: &[Statement]| well_typed(stmts) && fails(stmts);
+    loop {
+        let mut changed = false;
+
+        let mut i = 0;
+        while i < stmts.len() {
+            let mut candidate = stmts.clone();
+            candidate.remove(i);
+            if accepts(&candidate) {
+                stmts = candidate;
+                changed = true;
+            } else {
+                i += 1;
+            }
+        }
+
+        for i in 0..stmts.len() {
+            for simpler in shrink_stmt(&stmts[i]) {
+                let mut candidate = stmts.clone();
+                candidate[i] = simpler;
+                if accepts(&candidate) {
+                    stmts = candidate;
+                    changed = true;
+                    break;
+                }
+            }
+        }
+
+        if !changed {
+            return stmts;
+        }
+    }
+}
+
+// 一段階だけ単純にした文の候補
+fn shrink_stmt(stmt: &Statement) -> Vec<Statement> {
+    match stmt {
+        Statement::Assign(ident, expr) => shrink_expr(expr)
+            .into_iter()
+            .map(|expr| Statement::Assign(ident.clone(), expr))
+            .collect(),
+        Statement::Print(expr) => shrink_expr(expr)
+            .into_iter()
+            .map(Statement::Print)
+            .collect(),
+        Statement::Eval(expr) => shrink_expr(expr).into_iter().map(Statement::Eval).collect(),
+        Statement::If(cond, body_stmt) => {
+            let mut candidates = vec![body_stmt.as_ref().clone()];
+            candidates.extend(
+                shrink_expr(cond)
+                    .into_iter()
+                    .map(|cond| Statement::If(cond, body_stmt.clone())),
+            );
+            candidates.extend(
+                shrink_stmt(body_stmt)
+                    .into_iter()
+                    .map(|body_stmt| Statement::If(cond.clone(), Box::new(body_stmt))),
+            );
+            candidates
+        }
+    }
+}
+
+// 一段階だけ単純にした式の候補。型が合わない候補は呼び出し側で捨てる
+fn shrink_expr(expr: &Expression) -> Vec<Expression> {
+    let zero = Expression::Number(0u32.into());
+    match expr {
+        Expression::Number(num) => {
+            let num = u32::from(num);
+            if num == 0 {
+                Vec::new()
+            } else {
+                vec![zero, Expression::Number((num / 2).into())]
+            }
+        }
+        Expression::Identifier(_) | Expression::Call(_, _) => vec![zero],
+        Expression::Add(left, right) | Expression::Sub(left, right) => {
+            let rebuild = |left, right| match expr {
+                Expression::Add(_, _) => Expression::Add(Box::new(left), Box::new(right)),
+                _ => Expression::Sub(Box::new(left), Box::new(right)),
+            };
+            let mut candidates = vec![left.as_ref().clone(), right.as_ref().clone()];
+            candidates.extend(
+                shrink_expr(left)
+                    .into_iter()
+                    .map(|left| rebuild(left, right.as_ref().clone())),
+            );
+            candidates.extend(
+                shrink_expr(right)
+                    .into_iter()
+                    .map(|right| rebuild(left.as_ref().clone(), right)),
+            );
+            candidates
+        }
+        Expression::Comp(left, right) => {
+            let mut candidates: Vec<Expression> = shrink_expr(left)
+                .into_iter()
+                .map(|left| Expression::Comp(Box::new(left), right.clone()))
+                .collect();
+            candidates.extend(
+                shrink_expr(right)
+                    .into_iter()
+                    .map(|right| Expression::Comp(left.clone(), Box::new(right))),
+            );
+            candidates
+        }
+    }
+}
+
+#[test]
+fn differential_jit_test() {
+    // 環境変数 SIMPLELANG_FUZZ_ITERATIONS で生成するプログラムの数を増やせる
+    let iterations: u64 = env::var("SIMPLELANG_FUZZ_ITERATIONS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(50);
+    for seed in 0..iterations {
+        let program = Generator::new(seed).program(20);
+        assert!(
+            well_typed(&program),
+            "seed {seed}: generated an ill-typed program\n{}",
+            format_program(&program)
+        );
+        if check(&program).is_some() {
+            let minimized = minimize(program, |stmts| check(stmts).is_some());
+            panic!(
+                "seed {seed}: JIT and interpreter disagree on\n{}\n{}",
+                format_program(&minimized),
+                check(&minimized).unwrap()
+            );
+        }
+    }
+}
+
+#[test]
+fn minimize_test() {
+    let program = crate::parser::parse_program(
+        "a = 3\nb = a + 4\nprint b\nc = a == b\nif c then print (a + 7) - 0\nprint 1\n",
+    )
+    .unwrap();
+
+    // 7 を含む `print` 文があることを「失敗」とみなす
+    let contains_seven = |stmts: &[Statement]| {
+        format_program(stmts)
+            .lines()
+            .any(|line| line.contains("print") && line.contains('7'))
+    };
+    let minimized = minimize(program, contains_seven);
+    assert_eq!(format_program(&minimized), "print 7\n");
+}
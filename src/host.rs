@@ -0,0 +1,192 @@
+use crate::{lexer, type_::TypeRepr};
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    // 識別子でないか、予約語である関数名
+    InvalidName(String),
+    // 宣言した引数の数と関数ポインタの引数の数が異なる
+    ArityMismatch {
+        name: String,
+        params: usize,
+        arity: usize,
+    },
+    // 引数や戻り値の型は int か bool に限る
+    InvalidType(String, TypeRepr),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidName(name) => write!(f, "`{name}` is not a valid function name"),
+            Self::ArityMismatch {
+                name,
+                params,
+                arity,
+            } => write!(
+                f,
+                "function `{name}` is declared with {params} parameter(s) but takes {arity} argument(s)"
+            ),
+            Self::InvalidType(name, ty) => {
+                write!(f, "function `{name}` cannot take or return a value of type {ty}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// スクリプトから呼び出すホストの関数のポインタ。引数の数ごとに型が異なる
+// 引数と戻り値はどれも 4 バイトの整数で、真偽値は 0 か 1 として受け渡す
+#[derive(Debug, Clone, Copy)]
+pub enum HostFn {
+    Arity0(extern "C" fn() -> u32),
+    Arity1(extern "C" fn(u32) -> u32),
+    Arity2(extern "C" fn(u32, u32) -> u32),
+    Arity3(extern "C" fn(u32, u32, u32) -> u32),
+    Arity4(extern "C" fn(u32, u32, u32, u32) -> u32),
+}
+
+impl HostFn {
+    pub fn arity(&self) -> usize {
+        match self {
+            Self::Arity0(_) => 0,
+            Self::Arity1(_) => 1,
+            Self::Arity2(_) => 2,
+            Self::Arity3(_) => 3,
+            Self::Arity4(_) => 4,
+        }
+    }
+
+    pub(crate) fn addr(&self) -> *const u8 {
+        match *self {
+            Self::Arity0(func) => func as *const u8,
+            Self::Arity1(func) => func as *const u8,
+            Self::Arity2(func) => func as *const u8,
+            Self::Arity3(func) => func as *const u8,
+            Self::Arity4(func) => func as *const u8,
+        }
+    }
+
+    // インタプリタから呼び出す。引数の数は型検査で arity と一致している
+    pub(crate) fn call(&self, args: &[u32]) -> u32 {
+        match (*self, args) {
+            (Self::Arity0(func), []) => func(),
+            (Self::Arity1(func), [a]) => func(*a),
+            (Self::Arity2(func), [a, b]) => func(*a, *b),
+            (Self::Arity3(func), [a, b, c]) => func(*a, *b, *c),
+            (Self::Arity4(func), [a, b, c, d]) => func(*a, *b, *c, *d),
+            _ => panic!(
+                "function takes {} argument(s) but {} were given",
+                self.arity(),
+                args.len()
+            ),
+        }
+    }
+}
+
+// simplelang の型で宣言した、スクリプトから呼び出せるホストの関数
+#[derive(Debug, Clone)]
+pub struct HostFunction {
+    pub(crate) name: String,
+    pub(crate) params: Vec<TypeRepr>,
+    pub(crate) ret: TypeRepr,
+    pub(crate) func: HostFn,
+}
+
+impl HostFunction {
+    pub fn new(
+        name: &str,
+        params: &[TypeRepr],
+        ret: TypeRepr,
+        func: HostFn,
+    ) -> Result<Self, Error> {
+        let is_identifier = matches!(
+            lexer::tokenize(name).as_deref(),
+            Ok([token, eof]) if token.kind == lexer::TokenKind::Identifier(name.to_owned())
+                && eof.kind == lexer::TokenKind::Eof
+        );
+        if !is_identifier {
+            return Err(Error::InvalidName(name.to_owned()));
+        }
+        if params.len() != func.arity() {
+            return Err(Error::ArityMismatch {
+                name: name.to_owned(),
+                params: params.len(),
+                arity: func.arity(),
+            });
+        }
+        if let Some(ty) = params
+            .iter()
+            .chain([&ret])
+            .find(|ty| !matches!(ty, TypeRepr::Int | TypeRepr::Bool))
+        {
+            return Err(Error::InvalidType(name.to_owned(), *ty));
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            params: params.to_vec(),
+            ret,
+            func,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// モジュール内でのシンボル名
+// 識別子に含まれない `.` を付けて、`main` や `stmt0`、`println_u32` などとの衝突を避ける
+pub(crate) fn symbol_name(name: &str) -> String {
+    format!("host.{name}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, HostFn, HostFunction};
+    use crate::type_::TypeRepr;
+
+    extern "C" fn add(a: u32, b: u32) -> u32 {
+        a.wrapping_add(b)
+    }
+
+    #[test]
+    fn new_host_function_test() {
+        let func = HostFunction::new(
+            "add",
+            &[TypeRepr::Int, TypeRepr::Int],
+            TypeRepr::Int,
+            HostFn::Arity2(add),
+        )
+        .unwrap();
+        assert_eq!(func.func.call(&[40, 2]), 42);
+
+        for name in ["if", "a b", "1a", "", "a_b"] {
+            assert_eq!(
+                HostFunction::new(
+                    name,
+                    &[TypeRepr::Int; 2],
+                    TypeRepr::Int,
+                    HostFn::Arity2(add)
+                )
+                .unwrap_err(),
+                Error::InvalidName(name.to_owned())
+            );
+        }
+        assert!(matches!(
+            HostFunction::new("add", &[TypeRepr::Int], TypeRepr::Int, HostFn::Arity2(add)),
+            Err(Error::ArityMismatch { .. })
+        ));
+        assert_eq!(
+            HostFunction::new(
+                "add",
+                &[TypeRepr::Int; 2],
+                TypeRepr::Id(0),
+                HostFn::Arity2(add)
+            )
+            .unwrap_err(),
+            Error::InvalidType("add".to_owned(), TypeRepr::Id(0))
+        );
+    }
+}
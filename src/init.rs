@@ -0,0 +1,196 @@
+use crate::{expr::Expression, stmt::Statement};
+use std::{
+    collections::HashSet,
+    fmt::{self, Display, Formatter},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    // 一度も代入されていない変数の読み出し
+    Unassigned(String),
+    // `if` の本体でしか代入されていない変数の読み出し
+    MaybeUnassigned(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unassigned(name) => {
+                write!(f, "Init error: variable `{name}` is read before it is assigned")
+            }
+            Self::MaybeUnassigned(name) => write!(
+                f,
+                "Init error: variable `{name}` may not be assigned yet (it is only assigned inside an `if` body)"
+            ),
+        }
+    }
+}
+
+// 代入済みであることが確定している変数と、代入されている可能性がある変数の集合
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Assigned {
+    definite: HashSet<String>,
+    maybe: HashSet<String>,
+}
+
+impl Assigned {
+    pub fn new<I: IntoIterator<Item = String>>(names: I) -> Self {
+        Self {
+            definite: names.into_iter().collect(),
+            maybe: HashSet::new(),
+        }
+    }
+
+    // `if` の本体でしか代入されていない変数を maybe として指定する
+    pub fn with_maybe<I, J>(definite: I, maybe: J) -> Self
+    where
+        I: IntoIterator<Item = String>,
+        J: IntoIterator<Item = String>,
+    {
+        Self {
+            definite: definite.into_iter().collect(),
+            maybe: maybe.into_iter().collect(),
+        }
+    }
+
+    pub fn is_assigned(&self, name: &str) -> bool {
+        self.definite.contains(name)
+    }
+
+    pub fn analyze_stmt(&mut self, stmt: &Statement) -> Result<(), Error> {
+        // 検査に成功した場合だけ、文で代入された変数を加える
+        let mut local = Self::default();
+        Self::check_stmt(&[self], &mut local, stmt)?;
+        self.definite.extend(local.definite);
+        self.maybe.extend(local.maybe);
+        Ok(())
+    }
+
+    pub fn analyze_expr(&self, expr: &Expression) -> Result<(), Error> {
+        Self::check_expr(&[self], expr)
+    }
+
+    // outer は外側の文までに代入された変数、local はこの文で代入された変数
+    // `if` の本体ごとに集合を複製せずに済むよう、外側の集合は参照のまま重ねる
+    fn check_stmt(outer: &[&Self], local: &mut Self, stmt: &Statement) -> Result<(), Error> {
+        let mut scopes = outer.to_vec();
+        scopes.push(local);
+        match stmt {
+            Statement::Assign(ident, expr) => {
+                // 右辺を先に検査するため、`a = a + 1` の右辺の `a` は未代入として扱われる
+                Self::check_expr(&scopes, expr)?;
+                local.definite.insert(ident.to_string());
+            }
+            Statement::If(cond, body_stmt) => {
+                Self::check_expr(&scopes, cond)?;
+                let mut body = Self::default();
+                Self::check_stmt(&scopes, &mut body, body_stmt)?;
+                // 本体は実行されない可能性があるため、本体で代入された変数は「代入済みかもしれない」にとどめる
+                // 入れ子の `if` の本体で代入された変数も含める
+                let names: Vec<String> = body
+                    .definite
+                    .into_iter()
+                    .chain(body.maybe)
+                    .filter(|name| !scopes.iter().any(|scope| scope.definite.contains(name)))
+                    .collect();
+                local.maybe.extend(names);
+            }
+            Statement::Print(expr) | Statement::Eval(expr) => Self::check_expr(&scopes, expr)?,
+        }
+        Ok(())
+    }
+
+    fn check_expr(scopes: &[&Self], expr: &Expression) -> Result<(), Error> {
+        match expr {
+            Expression::Identifier(ident) => {
+                let name: &str = ident;
+                if scopes.iter().any(|scope| scope.definite.contains(name)) {
+                    Ok(())
+                } else if scopes.iter().any(|scope| scope.maybe.contains(name)) {
+                    Err(Error::MaybeUnassigned(name.to_owned()))
+                } else {
+                    Err(Error::Unassigned(name.to_owned()))
+                }
+            }
+            Expression::Number(_) => Ok(()),
+            Expression::Call(_, args) => args
+                .iter()
+                .try_for_each(|arg| Self::check_expr(scopes, arg)),
+            Expression::Comp(left, right)
+            | Expression::Add(left, right)
+            | Expression::Sub(left, right) => {
+                Self::check_expr(scopes, left)?;
+                Self::check_expr(scopes, right)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Assigned, Error};
+    use crate::{expr::*, stmt::*};
+
+    fn ident(name: &str) -> Expression {
+        Expression::Identifier(name.to_owned().into())
+    }
+
+    #[test]
+    fn self_reference_test() {
+        let mut assigned = Assigned::default();
+
+        let stmt = Statement::Assign(
+            "a".to_owned().into(),
+            Expression::Add(
+                Box::new(ident("a")),
+                Box::new(Expression::Number(1u32.into())),
+            ),
+        );
+        assert_eq!(
+            assigned.analyze_stmt(&stmt),
+            Err(Error::Unassigned("a".to_owned()))
+        );
+
+        let stmt = Statement::Assign("a".to_owned().into(), Expression::Number(1u32.into()));
+        assert!(assigned.analyze_stmt(&stmt).is_ok());
+        assert!(assigned.is_assigned("a"));
+    }
+
+    #[test]
+    fn if_body_assignment_test() {
+        let mut assigned = Assigned::new(["c".to_owned()]);
+
+        let stmt = Statement::If(
+            Expression::Comp(
+                Box::new(ident("c")),
+                Box::new(Expression::Number(1u32.into())),
+            ),
+            Box::new(Statement::Assign(
+                "x".to_owned().into(),
+                Expression::Number(1u32.into()),
+            )),
+        );
+        assert!(assigned.analyze_stmt(&stmt).is_ok());
+        assert!(!assigned.is_assigned("x"));
+        assert_eq!(
+            assigned.analyze_stmt(&Statement::Print(ident("x"))),
+            Err(Error::MaybeUnassigned("x".to_owned()))
+        );
+
+        let stmt = Statement::Assign("x".to_owned().into(), Expression::Number(2u32.into()));
+        assert!(assigned.analyze_stmt(&stmt).is_ok());
+        assert!(assigned.analyze_stmt(&Statement::Print(ident("x"))).is_ok());
+    }
+
+    #[test]
+    fn nested_if_body_assignment_test() {
+        let mut assigned = Assigned::new(["c".to_owned()]);
+        let stmt = crate::parser::parse("if c == 1 then if c == 1 then x = 1").unwrap();
+        assert!(assigned.analyze_stmt(&stmt).is_ok());
+        assert!(!assigned.is_assigned("x"));
+        assert_eq!(
+            assigned.analyze_stmt(&Statement::Print(ident("x"))),
+            Err(Error::MaybeUnassigned("x".to_owned()))
+        );
+    }
+}
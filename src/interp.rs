@@ -0,0 +1,194 @@
+use crate::{expr::Expression, host::HostFunction, stmt::Statement, type_::TypeRepr};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    io::{self, Stdout, Write},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    Int(u32),
+    Bool(bool),
+}
+
+impl Value {
+    // JIT では真偽値も 4 バイトの整数として扱うため、それに合わせた値を返す
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            Self::Int(n) => *n,
+            Self::Bool(b) => *b as u32,
+        }
+    }
+
+    pub fn ty(&self) -> TypeRepr {
+        match self {
+            Self::Int(_) => TypeRepr::Int,
+            Self::Bool(_) => TypeRepr::Bool,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(n) => write!(f, "{n}"),
+            Self::Bool(b) => write!(f, "{b}"),
+        }
+    }
+}
+
+// 文と式を直接実行するインタプリタ。JIT と同じ意味で実行する
+// 実行する文は Env で型検査されている必要がある
+pub struct Interpreter<W: Write = Stdout> {
+    pub(crate) variables: HashMap<String, Value>,
+    functions: HashMap<String, HostFunction>,
+    out: W,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::with_output(io::stdout())
+    }
+}
+
+impl<W: Write> Interpreter<W> {
+    // `print` の出力先を指定する
+    pub fn with_output(out: W) -> Self {
+        Self {
+            variables: HashMap::new(),
+            functions: HashMap::new(),
+            out,
+        }
+    }
+
+    pub fn output(&self) -> &W {
+        &self.out
+    }
+
+    // 代入済みでない変数には None を返す
+    pub fn variable(&self, name: &str) -> Option<Value> {
+        self.variables.get(name).copied()
+    }
+
+    pub fn set_variable(&mut self, name: &str, value: Value) {
+        self.variables.insert(name.to_owned(), value);
+    }
+
+    // ホストの関数を呼び出せるようにする。同じ名前の関数があれば置き換える
+    pub fn add_function(&mut self, func: &HostFunction) {
+        self.functions.insert(func.name.clone(), func.clone());
+    }
+
+    pub fn clear_variables(&mut self) {
+        self.variables.clear();
+    }
+
+    // 代入済みの変数の名前
+    pub fn variable_names(&self) -> impl Iterator<Item = &str> {
+        self.variables.keys().map(String::as_str)
+    }
+
+    pub fn exec_stmt(&mut self, stmt: &Statement) -> io::Result<()> {
+        match stmt {
+            Statement::Assign(ident, expr) => {
+                let value = self.eval_expr(expr);
+                self.variables.insert(ident.to_string(), value);
+            }
+            Statement::If(cond, body_stmt) => {
+                if self.eval_expr(cond) == Value::Bool(true) {
+                    self.exec_stmt(body_stmt)?;
+                }
+            }
+            Statement::Print(expr) => {
+                let value = self.eval_expr(expr).as_u32();
+                writeln!(self.out, "{value}")?;
+            }
+            Statement::Eval(expr) => {
+                self.eval_expr(expr);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn eval_expr(&self, expr: &Expression) -> Value {
+        match expr {
+            Expression::Identifier(ident) => {
+                let name: &str = ident;
+                // 読み出す変数が代入済みであることは Env が保証する
+                *self
+                    .variables
+                    .get(name)
+                    .unwrap_or_else(|| panic!("variable `{name}` is not assigned"))
+            }
+            Expression::Number(num) => Value::Int(num.into()),
+            Expression::Add(left, right) => {
+                Value::Int(self.eval_int(left).wrapping_add(self.eval_int(right)))
+            }
+            Expression::Sub(left, right) => {
+                Value::Int(self.eval_int(left).wrapping_sub(self.eval_int(right)))
+            }
+            Expression::Comp(left, right) => {
+                Value::Bool(self.eval_int(left) == self.eval_int(right))
+            }
+            Expression::Call(func_name, args) => {
+                let func_name: &str = func_name;
+                // 呼び出す関数が追加済みであることは Env が保証する
+                let func = self
+                    .functions
+                    .get(func_name)
+                    .unwrap_or_else(|| panic!("function `{func_name}` is not added"));
+                let args: Vec<u32> = args.iter().map(|arg| self.eval_int(arg)).collect();
+                let ret = func.func.call(&args);
+                match func.ret {
+                    TypeRepr::Bool => Value::Bool(ret != 0),
+                    _ => Value::Int(ret),
+                }
+            }
+        }
+    }
+
+    fn eval_int(&self, expr: &Expression) -> u32 {
+        self.eval_expr(expr).as_u32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Interpreter, Value};
+    use crate::parser;
+
+    fn run(source: &str) -> String {
+        let mut interp = Interpreter::with_output(Vec::new());
+        for stmt in parser::parse_program(source).unwrap() {
+            interp.exec_stmt(&stmt).unwrap();
+        }
+        String::from_utf8(interp.output().clone()).unwrap()
+    }
+
+    #[test]
+    fn exec_program_test() {
+        let output =
+            run("a = 1\nb = a == 1\nif b then print a + 41\nif a == 2 then print 0\nprint 0 - 1\n");
+        assert_eq!(output, "42\n4294967295\n");
+    }
+
+    #[test]
+    fn eval_expr_test() {
+        let mut interp = Interpreter::with_output(Vec::new());
+        interp
+            .exec_stmt(&parser::parse("a = 4294967295 + 2\n").unwrap())
+            .unwrap();
+        assert_eq!(interp.variables.get("a"), Some(&Value::Int(1)));
+
+        interp
+            .exec_stmt(&parser::parse("b = a - 1 == 0\n").unwrap())
+            .unwrap();
+        assert_eq!(interp.variables.get("b"), Some(&Value::Bool(true)));
+    }
+}
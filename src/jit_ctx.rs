@@ -1,70 +1,422 @@
-use cranelift::codegen::settings::{self, Configurable};
-use cranelift::codegen::Context;
-use cranelift::prelude::*;
+use crate::{
+    codegen::CodegenResult,
+    host::{self, HostFunction},
+    module_ctx::{self, EmitOptions, ModuleContext, OptLevel, Settings, VariableData},
+    type_::TypeRepr,
+};
 use cranelift_jit::{JITBuilder, JITModule};
-use cranelift_module::{default_libcall_names, DataDescription, DataId, FuncId, Linkage, Module};
-use std::collections::HashMap;
+use cranelift_module::{default_libcall_names, DataId, FuncId, Linkage, Module};
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+};
 
-fn println_u32(n: u32) {
-    println!("{n}");
+thread_local! {
+    // JIT のコードにはエラーを返せないため、`print` の出力に失敗したらここに記録する
+    static PRINT_ERROR: RefCell<Option<io::Error>> = const { RefCell::new(None) };
 }
 
-pub struct JITContext {
-    pub(crate) module: JITModule,
-    pub(crate) ctx: Context,
-    pub(crate) func_ctx: FunctionBuilderContext,
-    pub(crate) data_description: DataDescription,
-    pub(crate) variables: HashMap<String, DataId>,
-    pub(crate) print_func: FuncId,
-    pub(crate) stmt_index: usize,
+// C の ABI をまたいで unwind できないため、出力に失敗しても panic しない
+extern "C" fn println_u32(n: u32) {
+    if let Err(err) = writeln!(io::stdout(), "{n}") {
+        PRINT_ERROR.with(|error| {
+            error.borrow_mut().get_or_insert(err);
+        });
+    }
 }
 
-impl JITContext {
-    pub fn new() -> Self {
-        let mut flag_builder = settings::builder();
-        flag_builder.set("use_colocated_libcalls", "false").unwrap();
-        // FIXME set back to true once the x64 backend supports it.
-        flag_builder.set("is_pic", "false").unwrap();
-        let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
-            panic!("host machine is not supported: {}", msg);
-        });
-        let isa = isa_builder
-            .finish(settings::Flags::new(flag_builder))
-            .unwrap();
+// 既定の `print` の関数が出力に失敗していれば、そのエラーを取り出す
+pub fn take_print_error() -> Option<io::Error> {
+    PRINT_ERROR.with(|error| error.borrow_mut().take())
+}
 
-        let mut module = {
-            let mut jit_builder = JITBuilder::with_isa(isa, default_libcall_names());
-            let println_u32_addr: *const u8 = println_u32 as *const u8;
-            jit_builder.symbol("println_u32", println_u32_addr);
-            JITModule::new(jit_builder)
-        };
-        let mut sig_println_u32 = module.make_signature();
-        sig_println_u32.params.push(AbiParam::new(types::I32));
-        let func_println_u32 = module
-            .declare_function("println_u32", Linkage::Import, &sig_println_u32)
-            .unwrap();
+pub type JITContext = ModuleContext<JITModule>;
 
-        let ctx = module.make_context();
-        let func_ctx = FunctionBuilderContext::new();
-        let data_description = DataDescription::new();
+#[derive(Debug, Clone)]
+pub struct JITContextBuilder {
+    settings: Settings,
+    emit: EmitOptions,
+    print_func: extern "C" fn(u32),
+    pub(crate) functions: Vec<HostFunction>,
+}
 
+impl Default for JITContextBuilder {
+    fn default() -> Self {
         Self {
-            module,
-            ctx,
-            func_ctx,
-            data_description,
-            variables: HashMap::new(),
-            print_func: func_println_u32,
-            stmt_index: 0,
+            settings: Settings::default(),
+            emit: EmitOptions::default(),
+            print_func: println_u32,
+            functions: Vec::new(),
         }
     }
+}
+
+impl JITContextBuilder {
+    pub fn opt_level(mut self, opt_level: OptLevel) -> Self {
+        self.settings.opt_level = opt_level;
+        self
+    }
+
+    pub fn verifier(mut self, verifier: bool) -> Self {
+        self.settings.verifier = verifier;
+        self
+    }
+
+    pub fn is_pic(mut self, is_pic: bool) -> Self {
+        self.settings.is_pic = is_pic;
+        self
+    }
+
+    pub fn settings(mut self, settings: Settings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn emit(mut self, emit: EmitOptions) -> Self {
+        self.emit = emit;
+        self
+    }
+
+    // `print` 文で呼び出す関数を差し替える。既定では標準出力に書き出す
+    pub fn print_func(mut self, print_func: extern "C" fn(u32)) -> Self {
+        self.print_func = print_func;
+        self
+    }
+
+    // スクリプトから呼び出せるホストの関数を追加する。同じ名前の関数があれば置き換える
+    // 呼び出しの型検査には、同じ関数を Env::add_function で追加しておく必要がある
+    pub fn function(mut self, func: HostFunction) -> Self {
+        self.functions.retain(|other| other.name != func.name);
+        self.functions.push(func);
+        self
+    }
 
-    pub fn get_finalized_function(&mut self, func_id: FuncId) -> extern "C" fn() {
+    // ホストのマシンが Cranelift でサポートされていない場合は panic する
+    pub fn build(self) -> JITContext {
+        self.try_build().unwrap_or_else(|msg| panic!("{msg}"))
+    }
+
+    pub fn try_build(self) -> Result<JITContext, String> {
+        let mut jit_ctx = JITContext::with_module(self.build_module()?);
+        jit_ctx.emit = self.emit;
+        jit_ctx.bool_functions = self
+            .functions
+            .iter()
+            .filter(|func| func.ret == TypeRepr::Bool)
+            .map(|func| func.name.clone())
+            .collect();
+        // 一度モジュールを作れた設定なら、作り直しにも失敗しない
+        jit_ctx.new_module = Some(Box::new(move || self.build_module().unwrap()));
+        Ok(jit_ctx)
+    }
+
+    fn build_module(&self) -> Result<JITModule, String> {
+        let isa = module_ctx::native_isa(&self.settings)?;
+        let mut jit_builder = JITBuilder::with_isa(isa, default_libcall_names());
+        let println_u32_addr: *const u8 = self.print_func as *const u8;
+        jit_builder.symbol("println_u32", println_u32_addr);
+        for func in &self.functions {
+            jit_builder.symbol(host::symbol_name(&func.name), func.func.addr());
+        }
+        Ok(JITModule::new(jit_builder))
+    }
+}
+
+impl Default for JITContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JITContext {
+    pub fn new() -> Self {
+        Self::builder().build()
+    }
+
+    pub fn builder() -> JITContextBuilder {
+        JITContextBuilder::default()
+    }
+
+    pub fn get_finalized_function(&mut self, func_id: FuncId) -> CodegenResult<extern "C" fn()> {
         // Perform linking.
-        self.module.finalize_definitions().unwrap();
+        self.module.finalize_definitions()?;
 
         let raw_func_ptr = self.module.get_finalized_function(func_id);
         // Cast it to a rust function pointer type.
-        unsafe { std::mem::transmute::<_, extern "C" fn()>(raw_func_ptr) }
+        Ok(unsafe { std::mem::transmute::<*const u8, extern "C" fn()>(raw_func_ptr) })
+    }
+
+    // 値を返す関数（Codegen::codegen_expr で定義したもの）を得る
+    pub fn get_finalized_eval_function(
+        &mut self,
+        func_id: FuncId,
+    ) -> CodegenResult<extern "C" fn() -> u32> {
+        self.module.finalize_definitions()?;
+
+        let raw_func_ptr = self.module.get_finalized_function(func_id);
+        Ok(unsafe { std::mem::transmute::<*const u8, extern "C" fn() -> u32>(raw_func_ptr) })
+    }
+
+    // 現在のモジュールで定義した関数の数
+    pub fn function_count(&self) -> usize {
+        self.stmt_index
+    }
+
+    /// 変数の値を同じ設定の新しいモジュールに移し、これまでにコンパイルした関数のコードと
+    /// データを解放する。REPL のように、実行し終えた文の関数を二度と呼ばない場合に使う
+    ///
+    /// # Safety
+    ///
+    /// この JITContext から得た関数ポインタは、実行中であってはならず、この後で呼び出してもならない
+    pub unsafe fn compact(&mut self) {
+        let new_module = self
+            .new_module
+            .take()
+            .expect("JITContext must be built by JITContextBuilder");
+        let mut names: Vec<String> = self.variables.keys().cloned().collect();
+        names.sort();
+        let values: Vec<(String, u32)> = names
+            .into_iter()
+            .filter_map(|name| {
+                let value = self.variable_value(&name)?;
+                Some((name, value))
+            })
+            .collect();
+
+        let mut jit_ctx = JITContext::with_module(new_module());
+        jit_ctx.emit = self.emit;
+        jit_ctx.bool_functions = std::mem::take(&mut self.bool_functions);
+        jit_ctx.new_module = Some(new_module);
+        for (name, value) in values {
+            jit_ctx.set_variable_value(&name, value);
+        }
+        let old = std::mem::replace(self, jit_ctx);
+        old.module.free_memory();
+    }
+
+    // 変数に値を書き込む。未定義の変数は、その値で初期化したデータとして定義する
+    pub fn set_variable_value(&mut self, name: &str, value: u32) {
+        if let Some(data) = self.variables.get(name).copied() {
+            self.module.finalize_definitions().unwrap();
+            let (ptr, size) = self.module.get_finalized_data(data.value);
+            assert_eq!(size, 4);
+            // 変数のデータは書き込み可能として宣言している
+            unsafe { ptr.cast::<u32>().cast_mut().write_unaligned(value) };
+            self.write_stored_flag(data, true);
+            return;
+        }
+        let value = self.define_variable_data(&format!("var.{name}"), &value.to_ne_bytes());
+        let stored = self.define_variable_data(&format!("stored.{name}"), &[1]);
+        self.variables
+            .insert(name.to_owned(), VariableData { value, stored });
+    }
+
+    // 変数を値のない状態に戻す。未定義の変数は何もしない
+    pub fn unset_variable_value(&mut self, name: &str) {
+        if let Some(data) = self.variables.get(name).copied() {
+            self.module.finalize_definitions().unwrap();
+            self.write_stored_flag(data, false);
+        }
+    }
+
+    // 変数の現在の値を読み出す。真偽値は 0 か 1 として格納されている。
+    // 一度も代入されていない変数は None を返す
+    pub fn variable_value(&mut self, name: &str) -> Option<u32> {
+        let data = *self.variables.get(name)?;
+        self.module.finalize_definitions().unwrap();
+        let (ptr, size) = self.module.get_finalized_data(data.stored);
+        assert_eq!(size, 1);
+        // 代入済みかどうかのフラグは 1 バイトで確保している
+        if unsafe { ptr.read() } == 0 {
+            return None;
+        }
+        let (ptr, size) = self.module.get_finalized_data(data.value);
+        assert_eq!(size, 4);
+        // 変数のデータは 4 バイトで確保しており、JIT の関数が実行中でなければ書き換えられない
+        Some(unsafe { ptr.cast::<u32>().read_unaligned() })
+    }
+
+    fn write_stored_flag(&mut self, data: VariableData, stored: bool) {
+        let (ptr, size) = self.module.get_finalized_data(data.stored);
+        assert_eq!(size, 1);
+        // フラグのデータも書き込み可能として宣言している
+        unsafe { ptr.cast_mut().write(stored as u8) };
+    }
+
+    fn define_variable_data(&mut self, name: &str, init: &[u8]) -> DataId {
+        let data_id = self
+            .module
+            .declare_data(name, Linkage::Local, true, false)
+            .unwrap();
+        self.data_description.define(init.into());
+        self.module
+            .define_data(data_id, &self.data_description)
+            .unwrap();
+        self.data_description.clear();
+        data_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JITContext;
+    use crate::{
+        codegen::Codegen,
+        host::{HostFn, HostFunction},
+        module_ctx::{EmitOptions, OptLevel},
+        parser,
+        type_::TypeRepr,
+    };
+
+    #[test]
+    fn build_with_settings_test() {
+        for is_pic in [false, true] {
+            let mut jit_ctx = JITContext::builder()
+                .opt_level(OptLevel::SpeedAndSize)
+                .verifier(true)
+                .is_pic(is_pic)
+                .build();
+            let stmts = parser::parse_program("a = 1\nb = a + 2 == 3\n").unwrap();
+            let mut codegen = Codegen::new(&mut jit_ctx);
+            for func_ptr in codegen.codegen_program(&stmts).unwrap() {
+                func_ptr();
+            }
+            assert_eq!(jit_ctx.variable_value("a"), Some(1));
+            assert_eq!(jit_ctx.variable_value("b"), Some(1));
+            assert_eq!(jit_ctx.variable_value("c"), None);
+        }
+    }
+
+    #[test]
+    fn set_variable_value_test() {
+        let mut jit_ctx = JITContext::new();
+        jit_ctx.set_variable_value("a", 40);
+        let mut codegen = Codegen::new(&mut jit_ctx);
+        for func_ptr in codegen
+            .codegen_program(&parser::parse_program("b = a + 2").unwrap())
+            .unwrap()
+        {
+            func_ptr();
+        }
+        jit_ctx.set_variable_value("b", 7);
+        let mut codegen = Codegen::new(&mut jit_ctx);
+        for func_ptr in codegen
+            .codegen_program(&parser::parse_program("a = b + 1").unwrap())
+            .unwrap()
+        {
+            func_ptr();
+        }
+        assert_eq!(jit_ctx.variable_value("a"), Some(8));
+        assert_eq!(jit_ctx.variable_value("b"), Some(7));
+    }
+
+    #[test]
+    fn compact_test() {
+        let mut jit_ctx = JITContext::new();
+        for i in 0..3 {
+            let mut codegen = Codegen::new(&mut jit_ctx);
+            let source = "a = 40\nb = a == 40\nif b then a = a + 2\nprint a";
+            for func_ptr in codegen
+                .codegen_program(&parser::parse_program(source).unwrap())
+                .unwrap()
+            {
+                func_ptr();
+            }
+            assert_eq!(jit_ctx.function_count(), 4);
+            // SAFETY: 得た関数ポインタはすでに呼び出し終えている
+            unsafe { jit_ctx.compact() };
+            assert_eq!(jit_ctx.function_count(), 0, "{i}");
+            assert_eq!(jit_ctx.variable_value("a"), Some(42));
+            assert_eq!(jit_ctx.variable_value("b"), Some(1));
+        }
+
+        let expr = parser::parse_expr("a + 1").unwrap();
+        assert_eq!(
+            Codegen::new(&mut jit_ctx).codegen_eval(&expr).unwrap()(),
+            43
+        );
+    }
+
+    #[test]
+    fn emit_test() {
+        let emit = EmitOptions::parse("clif,clif-opt,asm").unwrap();
+        let mut jit_ctx = JITContext::builder().emit(emit).build();
+        jit_ctx.emitted = Some(Vec::new());
+        let stmts = parser::parse_program("a = 1 + 2").unwrap();
+        Codegen::new(&mut jit_ctx).codegen_program(&stmts).unwrap();
+        let emitted = jit_ctx.emitted.take().unwrap();
+        assert_eq!(emitted.len(), 3);
+        assert!(
+            emitted[0].starts_with("; stmt0: a = 1 + 2\n"),
+            "{}",
+            emitted[0]
+        );
+        assert!(emitted[0].contains("iadd"), "{}", emitted[0]);
+        assert!(emitted[1].starts_with("; stmt0 (optimized): a = 1 + 2\n"));
+        assert!(emitted[1].contains("function"), "{}", emitted[1]);
+        assert!(emitted[2].starts_with("; stmt0 (asm): a = 1 + 2\n"));
+        assert!(emitted[2].lines().count() > 2, "{}", emitted[2]);
+
+        // 指定しなければ何も出力しない
+        let mut jit_ctx = JITContext::new();
+        jit_ctx.emitted = Some(Vec::new());
+        Codegen::new(&mut jit_ctx).codegen_program(&stmts).unwrap();
+        assert_eq!(jit_ctx.emitted, Some(Vec::new()));
+    }
+
+    extern "C" fn add_or_zero(a: u32, b: u32, zero: u32) -> u32 {
+        if zero != 0 {
+            0
+        } else {
+            a.wrapping_add(b)
+        }
+    }
+
+    #[test]
+    fn host_function_test() {
+        let func = HostFunction::new(
+            "addOrZero",
+            &[TypeRepr::Int, TypeRepr::Int, TypeRepr::Bool],
+            TypeRepr::Int,
+            HostFn::Arity3(add_or_zero),
+        )
+        .unwrap();
+        let mut jit_ctx = JITContext::builder().function(func).build();
+        let source = "a = addOrZero(40, 2, 1 == 2)\nb = addOrZero(a, 1, a == 42)";
+        for _ in 0..2 {
+            let mut codegen = Codegen::new(&mut jit_ctx);
+            for func_ptr in codegen
+                .codegen_program(&parser::parse_program(source).unwrap())
+                .unwrap()
+            {
+                func_ptr();
+            }
+            assert_eq!(jit_ctx.variable_value("a"), Some(42));
+            assert_eq!(jit_ctx.variable_value("b"), Some(0));
+            // モジュールを作り直しても、同じ関数を呼び出せる
+            // SAFETY: 得た関数ポインタはすでに呼び出し終えている
+            unsafe { jit_ctx.compact() };
+        }
+    }
+
+    #[test]
+    fn eval_expr_test() {
+        let mut jit_ctx = JITContext::new();
+        let mut codegen = Codegen::new(&mut jit_ctx);
+        for func_ptr in codegen
+            .codegen_program(&parser::parse_program("a = 40").unwrap())
+            .unwrap()
+        {
+            func_ptr();
+        }
+        for (source, expected) in [
+            ("a + 2", 42),
+            ("a == 40", 1),
+            ("1 == 2", 0),
+            ("0 - 1", u32::MAX),
+        ] {
+            let expr = parser::parse_expr(source).unwrap();
+            assert_eq!(codegen.codegen_eval(&expr).unwrap()(), expected, "{source}");
+        }
     }
 }
@@ -0,0 +1,255 @@
+use crate::parser::{Error, ErrorKind};
+use std::{
+    fmt::{self, Display, Formatter},
+    iter::Peekable,
+    str::CharIndices,
+};
+
+// ソースコード中の位置（1 始まり）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pos {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Pos {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    Identifier(String),
+    Number(u32),
+    // 予約語
+    If,
+    Then,
+    Print,
+    // 記号
+    Plus,
+    Minus,
+    Assign,
+    EqEq,
+    LParen,
+    RParen,
+    Comma,
+    Comment(String),
+    Newline,
+    Eof,
+}
+
+impl TokenKind {
+    fn keyword(s: &str) -> Option<Self> {
+        match s {
+            "if" => Some(Self::If),
+            "then" => Some(Self::Then),
+            "print" => Some(Self::Print),
+            _ => None,
+        }
+    }
+}
+
+impl Display for TokenKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Identifier(name) => write!(f, "`{name}`"),
+            Self::Number(num) => write!(f, "`{num}`"),
+            Self::If => write!(f, "`if`"),
+            Self::Then => write!(f, "`then`"),
+            Self::Print => write!(f, "`print`"),
+            Self::Plus => write!(f, "`+`"),
+            Self::Minus => write!(f, "`-`"),
+            Self::Assign => write!(f, "`=`"),
+            Self::EqEq => write!(f, "`==`"),
+            Self::LParen => write!(f, "`(`"),
+            Self::RParen => write!(f, "`)`"),
+            Self::Comma => write!(f, "`,`"),
+            Self::Comment(_) => write!(f, "comment"),
+            Self::Newline => write!(f, "end of line"),
+            Self::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub pos: Pos,
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    line: usize,
+    line_start: usize,
+    // 最後に位置を求めたオフセットとその列。長い行で列を先頭から数え直さないようにする
+    last_offset: usize,
+    last_column: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            chars: source.char_indices().peekable(),
+            line: 1,
+            line_start: 0,
+            last_offset: 0,
+            last_column: 1,
+        }
+    }
+
+    // offset は同じ行の中で前回より後ろにある
+    fn pos(&mut self, offset: usize) -> Pos {
+        if self.last_offset < self.line_start {
+            self.last_offset = self.line_start;
+            self.last_column = 1;
+        }
+        self.last_column += self.source[self.last_offset..offset].chars().count();
+        self.last_offset = offset;
+        Pos {
+            line: self.line,
+            column: self.last_column,
+        }
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars
+            .peek()
+            .map_or(self.source.len(), |(offset, _)| *offset)
+    }
+
+    // 条件を満たす間、文字を読み進めて、読んだ範囲の文字列を返す
+    fn take_while(&mut self, start: usize, pred: impl Fn(char) -> bool) -> &'a str {
+        while self.chars.next_if(|(_, c)| pred(*c)).is_some() {}
+        let end = self.offset();
+        &self.source[start..end]
+    }
+
+    fn next_token(&mut self) -> Result<Token, Error> {
+        while self
+            .chars
+            .next_if(|(_, c)| *c == ' ' || *c == '\t')
+            .is_some()
+        {}
+
+        let Some((start, c)) = self.chars.next() else {
+            let offset = self.source.len();
+            return Ok(Token {
+                kind: TokenKind::Eof,
+                pos: self.pos(offset),
+            });
+        };
+        let pos = self.pos(start);
+        let kind = match c {
+            '\n' => self.newline(start),
+            '\r' if self.chars.next_if(|(_, c)| *c == '\n').is_some() => self.newline(start + 1),
+            '#' => {
+                let text = self.take_while(start, |c| c != '\n' && c != '\r');
+                TokenKind::Comment(text.trim_end().to_owned())
+            }
+            '+' => TokenKind::Plus,
+            '-' => TokenKind::Minus,
+            '(' => TokenKind::LParen,
+            ')' => TokenKind::RParen,
+            ',' => TokenKind::Comma,
+            '=' => {
+                if self.chars.next_if(|(_, c)| *c == '=').is_some() {
+                    TokenKind::EqEq
+                } else {
+                    TokenKind::Assign
+                }
+            }
+            c if c.is_ascii_digit() => {
+                let digits = self.take_while(start, |c| c.is_ascii_digit());
+                let num = digits.parse().map_err(|_| Error {
+                    pos,
+                    kind: ErrorKind::NumberTooLarge(digits.to_owned()),
+                })?;
+                TokenKind::Number(num)
+            }
+            c if c.is_ascii_alphabetic() => {
+                let word = self.take_while(start, |c| c.is_ascii_alphanumeric());
+                TokenKind::keyword(word).unwrap_or_else(|| TokenKind::Identifier(word.to_owned()))
+            }
+            c => {
+                return Err(Error {
+                    pos,
+                    kind: ErrorKind::UnexpectedChar(c),
+                })
+            }
+        };
+        Ok(Token { kind, pos })
+    }
+
+    // `offset` は改行文字 `\n` の位置
+    fn newline(&mut self, offset: usize) -> TokenKind {
+        self.line += 1;
+        self.line_start = offset + 1;
+        TokenKind::Newline
+    }
+}
+
+// ソースコードをトークン列に分割する。トークン列は必ず `TokenKind::Eof` で終わる
+pub fn tokenize(source: &str) -> Result<Vec<Token>, Error> {
+    let mut lexer = Lexer::new(source);
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.next_token()?;
+        let is_eof = token.kind == TokenKind::Eof;
+        tokens.push(token);
+        if is_eof {
+            return Ok(tokens);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tokenize, Pos, TokenKind};
+    use crate::parser::ErrorKind;
+
+    #[test]
+    fn tokenize_test() {
+        let tokens = tokenize("if a1==(2) then print x-1 # done  \r\nprinter = 3").unwrap();
+        let kinds: Vec<TokenKind> = tokens.into_iter().map(|token| token.kind).collect();
+        let expected = vec![
+            TokenKind::If,
+            TokenKind::Identifier("a1".to_owned()),
+            TokenKind::EqEq,
+            TokenKind::LParen,
+            TokenKind::Number(2),
+            TokenKind::RParen,
+            TokenKind::Then,
+            TokenKind::Print,
+            TokenKind::Identifier("x".to_owned()),
+            TokenKind::Minus,
+            TokenKind::Number(1),
+            TokenKind::Comment("# done".to_owned()),
+            TokenKind::Newline,
+            TokenKind::Identifier("printer".to_owned()),
+            TokenKind::Assign,
+            TokenKind::Number(3),
+            TokenKind::Eof,
+        ];
+        assert_eq!(kinds, expected);
+    }
+
+    #[test]
+    fn token_position_test() {
+        let tokens = tokenize("a = 1\r\n\tprint a").unwrap();
+        assert_eq!(tokens[4].kind, TokenKind::Print);
+        assert_eq!(tokens[4].pos, Pos { line: 2, column: 2 });
+    }
+
+    #[test]
+    fn tokenize_error_test() {
+        let err = tokenize("a = 1 * 2").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::UnexpectedChar('*'));
+        assert_eq!(err.pos, Pos { line: 1, column: 7 });
+
+        let err = tokenize("a = 4294967296").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::NumberTooLarge("4294967296".to_owned()));
+    }
+}
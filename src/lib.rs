@@ -1,7 +1,21 @@
 pub mod codegen;
+pub mod completion;
 pub mod env;
 pub mod expr;
+pub mod formatter;
+#[cfg(test)]
+mod fuzz;
+pub mod host;
+pub mod init;
+pub mod interp;
 pub mod jit_ctx;
+pub mod lexer;
+pub mod module_ctx;
+pub mod object_ctx;
+pub mod optimize;
 pub mod parser;
+pub mod session;
+pub mod session_file;
 pub mod stmt;
 pub mod type_;
+pub mod wasm;
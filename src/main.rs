@@ -1,43 +1,794 @@
-use simplelang::{codegen::Codegen, env::Env, jit_ctx::JITContext, parser};
-use std::io::{self, Write};
+use rustyline::{
+    completion::{Completer, FilenameCompleter, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::Validator,
+    CompletionType, Config, Context, Editor, Helper,
+};
+use simplelang::{
+    codegen::Codegen,
+    completion,
+    env::Env,
+    formatter,
+    jit_ctx::JITContext,
+    module_ctx::{EmitOptions, OptLevel, Settings},
+    object_ctx::{self, ObjectContext},
+    optimize,
+    parser::{self, Input},
+    session::{self, Session, Snapshot},
+    session_file,
+    stmt::Statement,
+    wasm,
+};
+use std::{
+    collections::VecDeque,
+    env,
+    fmt::{self, Display, Formatter},
+    fs,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    process,
+};
 
-fn main() {
-    let mut jit_ctx = JITContext::new();
-    let mut env = Env::new_empty();
+const USAGE: &str = "\
+Usage:
+    simplelang                        start the REPL
+    simplelang run FILE               run a script file
+    simplelang build FILE [-o OUTPUT] compile a script file to a native executable
+                                      or a WebAssembly module
+    simplelang fmt [--check] FILE...  format source files in place
 
-    println!("If you want to quit, please enter `quit` or `exit`.");
-    loop {
-        let mut buffer = String::new();
+Options:
+    --emit=KIND[,KIND...]  print intermediate results of compilation to stderr
+                           (clif: Cranelift IR, clif-opt: optimized Cranelift IR,
+                            asm: disassembled machine code)
+    --opt-level=LEVEL      Cranelift optimization level (none, speed, speed_and_size)
+    --verifier=on|off      run the Cranelift IR verifier (default: on)
+    --pic=on|off           generate position-independent code
+                           (default: off for the JIT, on for `build`)
+    --backend=jit|interp   execute with the Cranelift JIT or the interpreter
+                           (default: jit)
+    --batch                read the REPL input without prompts and stop at the first error
+                           (default when stdin is not a terminal)
+    --target=native|wasm   output format of `build` (default: native)
+                           (wasm: a module importing `env.print` and exporting `main`)";
 
-        print!("> ");
-        io::stdout().flush().unwrap();
-        io::stdin()
-            .read_line(&mut buffer)
-            .expect("Failed to read line.");
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum BackendKind {
+    #[default]
+    Jit,
+    Interp,
+}
 
-        if ["quit", "exit"].contains(&buffer.trim()) {
-            break;
+impl BackendKind {
+    fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "jit" => Ok(Self::Jit),
+            "interp" => Ok(Self::Interp),
+            other => Err(format!("unknown backend `{other}`")),
         }
-        if buffer.trim().is_empty() {
-            continue;
+    }
+}
+
+// `build` の出力形式
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    #[default]
+    Native,
+    Wasm,
+}
+
+impl Target {
+    fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "native" => Ok(Self::Native),
+            "wasm" => Ok(Self::Wasm),
+            other => Err(format!("unknown target `{other}`")),
+        }
+    }
+}
+
+// サブコマンドに共通のオプション
+#[derive(Debug, Default)]
+struct Options {
+    batch: bool,
+    backend: BackendKind,
+    target: Target,
+    emit: EmitOptions,
+    opt_level: OptLevel,
+    verifier: Option<bool>,
+    is_pic: Option<bool>,
+}
+
+impl Options {
+    // オプションを取り除いた残りの引数とともに返す
+    fn parse(args: impl Iterator<Item = String>) -> Result<(Self, Vec<String>), String> {
+        let mut options = Self::default();
+        let mut rest = Vec::new();
+        for arg in args {
+            if arg == "--batch" {
+                options.batch = true;
+            } else if let Some(kinds) = arg.strip_prefix("--emit=") {
+                options.emit = EmitOptions::parse(kinds)?;
+            } else if let Some(level) = arg.strip_prefix("--opt-level=") {
+                options.opt_level = OptLevel::parse(level)?;
+            } else if let Some(flag) = arg.strip_prefix("--verifier=") {
+                options.verifier = Some(parse_on_off(flag)?);
+            } else if let Some(backend) = arg.strip_prefix("--backend=") {
+                options.backend = BackendKind::parse(backend)?;
+            } else if let Some(target) = arg.strip_prefix("--target=") {
+                options.target = Target::parse(target)?;
+            } else if let Some(flag) = arg.strip_prefix("--pic=") {
+                options.is_pic = Some(parse_on_off(flag)?);
+            } else {
+                rest.push(arg);
+            }
         }
+        Ok((options, rest))
+    }
 
-        match parser::parse(buffer.trim_start()) {
-            Err(err) => {
+    // 指定されなかった設定には、Cranelift の既定値か default_is_pic を使う
+    fn settings(&self, default_is_pic: bool) -> Settings {
+        let default = Settings::default();
+        Settings {
+            opt_level: self.opt_level,
+            verifier: self.verifier.unwrap_or(default.verifier),
+            is_pic: self.is_pic.unwrap_or(default_is_pic),
+        }
+    }
+
+    fn session(&self) -> Result<Session, session::Error> {
+        match self.backend {
+            BackendKind::Jit => Session::with_jit(
+                JITContext::builder()
+                    .settings(self.settings(false))
+                    .emit(self.emit),
+            ),
+            BackendKind::Interp => Ok(Session::with_interpreter()),
+        }
+    }
+}
+
+fn parse_on_off(s: &str) -> Result<bool, String> {
+    match s {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        other => Err(format!("expected `on` or `off`, found `{other}`")),
+    }
+}
+
+fn main() {
+    let (options, args) = match Options::parse(env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("{err}\n\n{USAGE}");
+            process::exit(EXIT_USAGE);
+        }
+    };
+    match args.first().map(String::as_str) {
+        None => process::exit(repl(&options)),
+        Some("run") => process::exit(run(&options, &args[1..])),
+        Some("build") => process::exit(build(&options, &args[1..])),
+        Some("fmt") => process::exit(fmt(&args[1..])),
+        Some("-h" | "--help") => println!("{USAGE}"),
+        Some(other) => {
+            eprintln!("unknown command `{other}`\n\n{USAGE}");
+            process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+const COMMANDS: &[&str] = &[
+    "asm", "help", "ir", "load", "reset", "restore", "save", "type", "undo", "vars",
+];
+
+const REPL_HELP: &str = "\
+Commands:
+    :type EXPR    show the type of an expression
+    :vars         list variables with their types and current values
+    :undo         revert the last statement (or `:load`)
+    :reset        forget all variables
+    :load FILE    run a script file in this session
+    :save FILE    save the variables with their types and values
+    :restore FILE replace the session with the variables saved by `:save`
+    :ir [opt]     toggle the (optimized) Cranelift IR dump
+    :asm          toggle the disassembly of generated machine code
+    :help         show this message
+    quit, exit    quit the REPL";
+
+// 端末から対話的に、またはバッチモードで入力を読んで実行する
+// バッチモードではプロンプトなどを表示せず、最初のエラーでその種類に応じた終了コードを返す
+fn repl(options: &Options) -> i32 {
+    let batch = options.batch || !io::stdin().is_terminal();
+    let mut repl = match Repl::new(options) {
+        Ok(repl) => repl,
+        Err(err) => {
+            eprintln!("{err}");
+            return err.exit_code();
+        }
+    };
+    let mut reader = if batch {
+        LineReader::Batch(io::stdin().lines())
+    } else {
+        LineReader::editor()
+    };
+
+    if !batch {
+        println!("If you want to quit, please enter `quit` or `exit`.");
+        println!("Enter `:help` to list the commands.");
+    }
+    // 複数行にわたる入力のうち、これまでに読んだ行
+    let mut buffer = String::new();
+    let mut status = 0;
+    let mut at_eof = false;
+    loop {
+        let prompt = if buffer.is_empty() { "> " } else { "... " };
+        let line = match reader.read_line(prompt, &mut repl) {
+            ReadLine::Line(line) => line,
+            // Ctrl-C で入力途中の文を破棄する
+            ReadLine::Interrupted => {
+                buffer.clear();
+                continue;
+            }
+            // 入力が文の途中で終わった場合は、そのまま解析してエラーを報告する
+            ReadLine::Eof if !buffer.is_empty() && !at_eof => {
+                at_eof = true;
+                String::new()
+            }
+            ReadLine::Eof => break,
+            ReadLine::Error(err) => {
                 eprintln!("{err}");
+                status = EXIT_FAILURE;
+                break;
+            }
+        };
+
+        let result = if buffer.is_empty() && line.trim_start().starts_with(':') {
+            reader.add_history_entry(line.trim());
+            repl.run_command(&line.trim()[1..])
+        } else if buffer.is_empty()
+            && (line.trim().is_empty() || line.trim_start().starts_with('#'))
+        {
+            continue;
+        } else if buffer.is_empty() && ["quit", "exit"].contains(&line.trim()) {
+            break;
+        } else if !at_eof && line.trim().is_empty() {
+            // 入力途中の空行は、入力途中の文を破棄する
+            buffer.clear();
+            continue;
+        } else {
+            if !at_eof {
+                buffer.push_str(&line);
+                buffer.push('\n');
+            }
+            let result = parser::parse_input(buffer.trim_start());
+            // 入力が途中で終わっていれば、空行が入力されるまで続きの行を読む
+            if matches!(&result, Err(err) if err.is_incomplete()) && !at_eof {
                 continue;
             }
-            Ok(stmt) => {
-                if let Err(err) = env.analyze_stmt(&stmt) {
-                    eprintln!("{err}");
-                    continue;
+            reader.add_history_entry(buffer.trim());
+            buffer.clear();
+            result
+                .map_err(ReplError::from)
+                .and_then(|input| repl.exec_input(input))
+        };
+
+        if let Err(err) = result {
+            eprintln!("{err}");
+            if batch {
+                status = err.exit_code();
+                break;
+            }
+        }
+    }
+
+    reader.save_history();
+    status
+}
+
+enum ReadLine {
+    Line(String),
+    Interrupted,
+    Eof,
+    Error(String),
+}
+
+// REPL の入力元
+enum LineReader {
+    // 端末からの入力。行編集、履歴と補完が使える
+    Editor(Box<Editor<ReplHelper, DefaultHistory>>, Option<PathBuf>),
+    // パイプなどからの入力
+    Batch(io::Lines<io::StdinLock<'static>>),
+}
+
+impl LineReader {
+    fn editor() -> Self {
+        // 補完の候補が複数ある場合は、共通部分まで補完して一覧を表示する
+        let config = Config::builder()
+            .completion_type(CompletionType::List)
+            .build();
+        let mut editor: Editor<ReplHelper, DefaultHistory> =
+            Editor::with_config(config).expect("Failed to initialize the line editor.");
+        editor.set_helper(Some(ReplHelper::default()));
+        let history = history_path();
+        if let Some(path) = &history {
+            // 初回の起動時には履歴ファイルがまだない
+            let _ = editor.load_history(path);
+        }
+        Self::Editor(Box::new(editor), history)
+    }
+
+    // 端末からの入力では、プロンプトを表示し、現在の変数名で補完する
+    fn read_line(&mut self, prompt: &str, repl: &mut Repl) -> ReadLine {
+        match self {
+            Self::Editor(editor, _) => {
+                if let Some(helper) = editor.helper_mut() {
+                    helper.names = repl.variable_names();
                 }
+                match editor.readline(prompt) {
+                    Ok(line) => ReadLine::Line(line),
+                    Err(ReadlineError::Interrupted) => ReadLine::Interrupted,
+                    Err(ReadlineError::Eof) => ReadLine::Eof,
+                    Err(err) => ReadLine::Error(err.to_string()),
+                }
+            }
+            Self::Batch(lines) => match lines.next() {
+                Some(Ok(line)) => ReadLine::Line(line),
+                Some(Err(err)) => ReadLine::Error(err.to_string()),
+                None => ReadLine::Eof,
+            },
+        }
+    }
 
-                let mut codegen = Codegen::new(&mut jit_ctx);
-                let func_ptr = codegen.codegen(&stmt);
-                // Call it!
-                func_ptr();
+    fn add_history_entry(&mut self, entry: &str) {
+        if let Self::Editor(editor, _) = self {
+            let _ = editor.add_history_entry(entry);
+        }
+    }
+
+    fn save_history(&mut self) {
+        if let Self::Editor(editor, Some(path)) = self {
+            if let Err(err) = editor.save_history(path) {
+                eprintln!("{}: {err}", path.display());
+            }
+        }
+    }
+}
+
+// REPL の入力を補完する
+#[derive(Default)]
+struct ReplHelper {
+    // 補完の候補にする変数名
+    names: Vec<String>,
+    files: FilenameCompleter,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, words) = match line.strip_prefix(':') {
+            Some(command) if command.starts_with("load ") => {
+                return self.files.complete(line, pos, ctx);
             }
+            Some(_) => completion::complete_command(line, pos, COMMANDS),
+            None => completion::complete(line, pos, &self.names),
+        };
+        let pairs = words
+            .into_iter()
+            .map(|word| Pair {
+                display: word.clone(),
+                replacement: word,
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+// 履歴ファイルの場所。環境変数 `SIMPLELANG_HISTORY` で変更できる
+fn history_path() -> Option<PathBuf> {
+    env::var_os("SIMPLELANG_HISTORY")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".simplelang_history")))
+}
+
+// 終了コード
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_PARSE_ERROR: i32 = 3;
+const EXIT_TYPE_ERROR: i32 = 4;
+const EXIT_RUNTIME_ERROR: i32 = 5;
+
+// REPL の入力の処理に失敗した理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    Parse,
+    // 型エラー、未定義の変数、未代入の変数の読み出し
+    Type,
+    Runtime,
+    // コマンドの誤りやファイルの読み書きの失敗
+    Command,
+}
+
+#[derive(Debug)]
+struct ReplError(Failure, String);
+
+impl ReplError {
+    fn command(message: impl Into<String>) -> Self {
+        Self(Failure::Command, message.into())
+    }
+
+    // エラーメッセージの前にファイル名を付ける
+    fn in_file(self, path: &str) -> Self {
+        Self(self.0, format!("{path}: {}", self.1))
+    }
+
+    fn exit_code(&self) -> i32 {
+        match self.0 {
+            Failure::Parse => EXIT_PARSE_ERROR,
+            Failure::Type => EXIT_TYPE_ERROR,
+            Failure::Runtime => EXIT_RUNTIME_ERROR,
+            Failure::Command => EXIT_FAILURE,
+        }
+    }
+}
+
+impl Display for ReplError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.1)
+    }
+}
+
+impl From<parser::Error> for ReplError {
+    fn from(err: parser::Error) -> Self {
+        Self(Failure::Parse, err.to_string())
+    }
+}
+
+impl From<session::Error> for ReplError {
+    fn from(err: session::Error) -> Self {
+        let failure = match err {
+            session::Error::Parse(_) | session::Error::InvalidVariable(_) => Failure::Parse,
+            session::Error::Type(_) => Failure::Type,
+            session::Error::Runtime(_) | session::Error::Compile(_) => Failure::Runtime,
+        };
+        Self(failure, err.to_string())
+    }
+}
+
+impl From<session_file::Error> for ReplError {
+    fn from(err: session_file::Error) -> Self {
+        Self(Failure::Parse, err.to_string())
+    }
+}
+
+// REPL のセッションの状態
+struct Repl {
+    session: Session,
+    // `:undo` で戻るための、実行した入力ごとの実行前の状態
+    // スナップショットは変数をすべて複製するため、新しい UNDO_LIMIT 個だけを残す
+    undo_stack: VecDeque<Checkpoint>,
+}
+
+const UNDO_LIMIT: usize = 100;
+
+struct Checkpoint {
+    // `:undo` で表示する、取り消す入力の説明
+    label: String,
+    snapshot: Snapshot,
+}
+
+impl Repl {
+    fn new(options: &Options) -> Result<Self, ReplError> {
+        Ok(Self {
+            session: options.session()?,
+            undo_stack: VecDeque::new(),
+        })
+    }
+
+    fn variable_names(&mut self) -> Vec<String> {
+        self.session.variable_names()
+    }
+
+    fn exec_input(&mut self, input: Input) -> Result<(), ReplError> {
+        match input {
+            Input::Statement(stmt) => {
+                let label = format!("`{stmt}`");
+                self.exec_stmts(&[stmt], label)
+            }
+            Input::Expression(expr) => {
+                let value = self.session.eval_expr(&expr)?;
+                println!("{value} : {}", value.ty());
+                Ok(())
+            }
+        }
+    }
+
+    // すべての文の型検査に成功した場合だけ、最適化してから順に実行する
+    // label は `:undo` で取り消す入力の説明
+    fn exec_stmts(&mut self, stmts: &[Statement], label: String) -> Result<(), ReplError> {
+        let snapshot = self.session.snapshot();
+        let result = self.session.exec_program(stmts);
+        for warning in self.session.take_warnings() {
+            eprintln!("{warning}");
+        }
+        // 型検査に失敗した場合は何も実行していないため、取り消す必要がない
+        if !matches!(
+            result,
+            Err(session::Error::Parse(_) | session::Error::Type(_))
+        ) {
+            if self.undo_stack.len() == UNDO_LIMIT {
+                self.undo_stack.pop_front();
+            }
+            self.undo_stack.push_back(Checkpoint { label, snapshot });
+        }
+        Ok(result?)
+    }
+
+    // `:` で始まる REPL のコマンドを実行する
+    fn run_command(&mut self, command: &str) -> Result<(), ReplError> {
+        let (name, arg) = command
+            .split_once(char::is_whitespace)
+            .map_or((command, ""), |(name, arg)| (name, arg.trim()));
+        match (name, arg) {
+            ("ir", "" | "opt") | ("asm", "") => {
+                let Some(emit) = self.session.emit_options() else {
+                    return Err(ReplError::command(format!(
+                        "`:{command}` is not available with the interpreter backend"
+                    )));
+                };
+                let (flag, name) = match (name, arg) {
+                    ("ir", "") => (&mut emit.clif, "IR dump"),
+                    ("ir", _) => (&mut emit.clif_opt, "optimized IR dump"),
+                    _ => (&mut emit.asm, "disassembly"),
+                };
+                *flag = !*flag;
+                println!("{name}: {}", on_off(*flag));
+            }
+            ("type", expr) if !expr.is_empty() => {
+                let ty = self.session.type_of(&parser::parse_expr(expr)?)?;
+                println!("{expr} : {ty}");
+            }
+            ("vars", "") => {
+                let vars = self.session.variables();
+                if vars.is_empty() {
+                    println!("no variables");
+                }
+                for var in vars {
+                    let session_file::Variable { name, ty, .. } = &var;
+                    match var.value {
+                        Some(value) => println!("{name} : {ty} = {value}"),
+                        None => println!("{name} : {ty} (unassigned)"),
+                    }
+                }
+            }
+            ("reset", "") => {
+                self.session.reset()?;
+                self.undo_stack.clear();
+                println!("session reset");
+            }
+            ("load", path) if !path.is_empty() => {
+                let load = |repl: &mut Self| {
+                    let source = fs::read_to_string(path)
+                        .map_err(|err| ReplError::command(err.to_string()))?;
+                    let stmts = parser::parse_program(&source)?;
+                    repl.exec_stmts(&stmts, format!("`:load {path}`"))
+                };
+                load(self).map_err(|err| err.in_file(path))?;
+            }
+            ("save", path) if !path.is_empty() => {
+                let vars = self.session.variables();
+                fs::write(path, session_file::write_session(&vars))
+                    .map_err(|err| ReplError::command(err.to_string()).in_file(path))?;
+                println!("saved {} variables to {path}", vars.len());
+            }
+            ("restore", path) if !path.is_empty() => {
+                let source = fs::read_to_string(path)
+                    .map_err(|err| ReplError::command(err.to_string()).in_file(path))?;
+                let vars = session_file::read_session(&source)
+                    .map_err(|err| ReplError::from(err).in_file(path))?;
+                self.session.restore_variables(&vars)?;
+                self.undo_stack.clear();
+                println!("restored {} variables from {path}", vars.len());
+            }
+            ("undo", "") => {
+                let checkpoint = self
+                    .undo_stack
+                    .pop_back()
+                    .ok_or_else(|| ReplError::command("nothing to undo"))?;
+                self.session.rollback(checkpoint.snapshot);
+                println!("undid {}", checkpoint.label);
+            }
+            ("help", "") => println!("{REPL_HELP}"),
+            _ => {
+                return Err(ReplError::command(format!(
+                    "unknown command `:{command}`; enter `:help` to list the commands"
+                )))
+            }
+        }
+        Ok(())
+    }
+}
+
+fn on_off(flag: bool) -> &'static str {
+    if flag {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+// スクリプト全体を読み込んで解析する
+fn parse_file(path: &str) -> Result<Vec<Statement>, i32> {
+    let source = fs::read_to_string(path).map_err(|err| {
+        eprintln!("{path}: {err}");
+        EXIT_FAILURE
+    })?;
+    parser::parse_program(&source).map_err(|err| {
+        eprintln!("{path}: {err}");
+        EXIT_PARSE_ERROR
+    })
+}
+
+// スクリプト全体を読み込み、解析・型検査してから最適化する
+fn load_program(path: &str) -> Result<Vec<Statement>, i32> {
+    let stmts = parse_file(path)?;
+
+    let mut env = Env::new_empty();
+    for stmt in &stmts {
+        env.analyze_stmt(stmt).map_err(|err| {
+            eprintln!("{path}: {err}");
+            EXIT_TYPE_ERROR
+        })?;
+    }
+
+    let mut warnings = Vec::new();
+    let stmts = stmts
+        .iter()
+        .filter_map(|stmt| optimize::optimize_stmt(stmt, &mut warnings))
+        .collect();
+    for warning in warnings {
+        eprintln!("{path}: {warning}");
+    }
+    Ok(stmts)
+}
+
+// 型検査を終えてから、すべての文をコンパイルして順に実行する
+fn run(options: &Options, args: &[String]) -> i32 {
+    let [path] = args else {
+        eprintln!("{USAGE}");
+        return EXIT_USAGE;
+    };
+    let stmts = match parse_file(path) {
+        Ok(stmts) => stmts,
+        Err(status) => return status,
+    };
+
+    // 型検査と最適化は exec_program が一度だけ行う
+    let mut session = match options.session() {
+        Ok(session) => session,
+        Err(err) => {
+            eprintln!("{path}: {err}");
+            return ReplError::from(err).exit_code();
+        }
+    };
+    let result = session.exec_program(&stmts);
+    for warning in session.take_warnings() {
+        eprintln!("{path}: {warning}");
+    }
+    if let Err(err) = result {
+        eprintln!("{path}: {err}");
+        return ReplError::from(err).exit_code();
+    }
+    0
+}
+
+// スクリプトをオブジェクトファイルにコンパイルし、ランタイムとリンクして実行ファイルを作る
+// `--target=wasm` の場合は WebAssembly モジュールを書き出す
+fn build(options: &Options, args: &[String]) -> i32 {
+    let (path, output) = match args {
+        [path] => match options.target {
+            Target::Native => (path, Path::new(path).with_extension("")),
+            Target::Wasm => (path, Path::new(path).with_extension("wasm")),
+        },
+        [path, flag, output] | [flag, output, path] if flag == "-o" => (path, output.into()),
+        _ => {
+            eprintln!("{USAGE}");
+            return EXIT_USAGE;
+        }
+    };
+    if output == Path::new(path) {
+        eprintln!("{path}: output file would overwrite the script; use `-o`");
+        return EXIT_USAGE;
+    }
+    let stmts = match load_program(path) {
+        Ok(stmts) => stmts,
+        Err(status) => return status,
+    };
+
+    if options.target == Target::Wasm {
+        if let Err(err) = fs::write(&output, wasm::compile_program(&stmts)) {
+            eprintln!("{}: {err}", output.display());
+            return EXIT_FAILURE;
+        }
+        return 0;
+    }
+
+    // 実行ファイルは PIE としてリンクされうるため、既定では位置独立なコードを生成する
+    let mut object_ctx = ObjectContext::new(path, &options.settings(true));
+    object_ctx.emit = options.emit;
+    let mut codegen = Codegen::new(&mut object_ctx);
+    let result = stmts
+        .iter()
+        .map(|stmt| codegen.codegen_stmt(stmt))
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|func_ids| codegen.codegen_main(&func_ids));
+    if let Err(err) = result {
+        eprintln!("{path}: {}", session::Error::from(err));
+        return EXIT_FAILURE;
+    }
+
+    if let Err(err) = object_ctx::link_executable(&object_ctx.finish(), &output) {
+        eprintln!("{}: {err}", output.display());
+        return EXIT_FAILURE;
+    }
+    0
+}
+
+// ファイルを整形して上書きする。`--check` の場合は上書きせず、整形が必要なファイルを報告する
+fn fmt(args: &[String]) -> i32 {
+    let check = args.iter().any(|arg| arg == "--check");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+    if paths.is_empty() {
+        eprintln!("{USAGE}");
+        return EXIT_USAGE;
+    }
+
+    let mut status = 0;
+    for path in paths {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("{path}: {err}");
+                status = EXIT_FAILURE;
+                continue;
+            }
+        };
+        let formatted = match formatter::format_source(&source) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                eprintln!("{path}: {err}");
+                status = EXIT_PARSE_ERROR;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{path}: not formatted");
+            status = EXIT_FAILURE;
+        } else if let Err(err) = fs::write(path, formatted) {
+            eprintln!("{path}: {err}");
+            status = EXIT_FAILURE;
         }
     }
+    status
 }
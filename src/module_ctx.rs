@@ -0,0 +1,204 @@
+use cranelift::codegen::isa::OwnedTargetIsa;
+use cranelift::codegen::settings::{self, Configurable};
+use cranelift::codegen::Context;
+use cranelift::prelude::*;
+use cranelift_module::{DataDescription, DataId, FuncId, Linkage, Module};
+use std::collections::{HashMap, HashSet};
+
+// コンパイル時に標準エラー出力へ書き出す中間結果
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EmitOptions {
+    // 最適化前の Cranelift IR
+    pub clif: bool,
+    // 最適化パスを通した後の Cranelift IR
+    pub clif_opt: bool,
+    // 生成した機械語の逆アセンブル
+    pub asm: bool,
+}
+
+impl EmitOptions {
+    // `clif,clif-opt` のようなカンマ区切りの指定を読む
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut options = Self::default();
+        for kind in s.split(',') {
+            match kind {
+                "clif" => options.clif = true,
+                "clif-opt" => options.clif_opt = true,
+                "asm" => options.asm = true,
+                other => return Err(format!("unknown emit kind `{other}`")),
+            }
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OptLevel {
+    #[default]
+    None,
+    Speed,
+    SpeedAndSize,
+}
+
+impl OptLevel {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "none" => Ok(Self::None),
+            "speed" => Ok(Self::Speed),
+            "speed_and_size" => Ok(Self::SpeedAndSize),
+            other => Err(format!("unknown optimization level `{other}`")),
+        }
+    }
+
+    // Cranelift の `opt_level` 設定の値
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Speed => "speed",
+            Self::SpeedAndSize => "speed_and_size",
+        }
+    }
+}
+
+// Cranelift のコード生成の設定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub opt_level: OptLevel,
+    // IR の検証器を有効にする。コンパイルは遅くなるが、不正な IR を検出できる
+    pub verifier: bool,
+    // 位置独立なコードを生成する
+    pub is_pic: bool,
+}
+
+impl Default for Settings {
+    // Cranelift の既定値に合わせる
+    fn default() -> Self {
+        Self {
+            opt_level: OptLevel::None,
+            verifier: true,
+            is_pic: false,
+        }
+    }
+}
+
+// 変数ごとに確保するデータ
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct VariableData {
+    // 4 バイトの値。真偽値は 0 か 1
+    pub(crate) value: DataId,
+    // 1 バイトのフラグ。一度でも代入を実行したら 1 になる
+    // `if` の本体の代入は、コンパイルしても実行されるとは限らないため区別する
+    pub(crate) stored: DataId,
+}
+
+// JIT とオブジェクトファイル出力で共通の、コード生成に必要な状態
+pub struct ModuleContext<M: Module> {
+    pub emit: EmitOptions,
+    pub(crate) module: M,
+    pub(crate) ctx: Context,
+    pub(crate) func_ctx: FunctionBuilderContext,
+    pub(crate) data_description: DataDescription,
+    pub(crate) variables: HashMap<String, VariableData>,
+    pub(crate) print_func: FuncId,
+    // インポートとして宣言済みのホストの関数
+    pub(crate) host_functions: HashMap<String, FuncId>,
+    // 戻り値が真偽値のホストの関数。戻り値を 0 か 1 に正規化する
+    pub(crate) bool_functions: HashSet<String>,
+    pub(crate) stmt_index: usize,
+    // Some の場合、emit で指定した IR などを標準エラー出力の代わりにここへ集める
+    pub(crate) emitted: Option<Vec<String>>,
+    // 同じ設定で空のモジュールを作る。JIT でモジュールを作り直す際に使う
+    pub(crate) new_module: Option<Box<dyn Fn() -> M>>,
+}
+
+impl<M: Module> ModuleContext<M> {
+    pub(crate) fn with_module(mut module: M) -> Self {
+        let mut sig_println_u32 = module.make_signature();
+        sig_println_u32.params.push(AbiParam::new(types::I32));
+        let func_println_u32 = module
+            .declare_function("println_u32", Linkage::Import, &sig_println_u32)
+            .unwrap();
+
+        let ctx = module.make_context();
+        let func_ctx = FunctionBuilderContext::new();
+        let data_description = DataDescription::new();
+
+        Self {
+            emit: EmitOptions::default(),
+            module,
+            ctx,
+            func_ctx,
+            data_description,
+            variables: HashMap::new(),
+            print_func: func_println_u32,
+            host_functions: HashMap::new(),
+            bool_functions: HashSet::new(),
+            stmt_index: 0,
+            emitted: None,
+            new_module: None,
+        }
+    }
+
+    // emit で指定した IR などを出力する
+    pub(crate) fn write_emitted(&mut self, text: String) {
+        match &mut self.emitted {
+            Some(emitted) => emitted.push(text),
+            None => eprintln!("{text}"),
+        }
+    }
+
+    // 定義済みの変数の名前
+    pub fn variable_names(&self) -> impl Iterator<Item = &str> {
+        self.variables.keys().map(String::as_str)
+    }
+}
+
+// ホストのマシンが Cranelift でサポートされていない場合は失敗する
+pub(crate) fn native_isa(config: &Settings) -> Result<OwnedTargetIsa, String> {
+    let mut flag_builder = settings::builder();
+    flag_builder.set("use_colocated_libcalls", "false").unwrap();
+    flag_builder
+        .set("opt_level", config.opt_level.as_str())
+        .unwrap();
+    flag_builder
+        .set("enable_verifier", &config.verifier.to_string())
+        .unwrap();
+    flag_builder
+        .set("is_pic", &config.is_pic.to_string())
+        .unwrap();
+    let isa_builder = cranelift_native::builder()
+        .map_err(|msg| format!("host machine is not supported: {msg}"))?;
+    isa_builder
+        .finish(settings::Flags::new(flag_builder))
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmitOptions, OptLevel};
+
+    #[test]
+    fn parse_emit_options_test() {
+        let options = EmitOptions::parse("clif").unwrap();
+        assert!(options.clif && !options.clif_opt);
+
+        let options = EmitOptions::parse("clif,clif-opt").unwrap();
+        assert!(options.clif && options.clif_opt);
+
+        let options = EmitOptions::parse("asm").unwrap();
+        assert!(options.asm && !options.clif);
+
+        assert!(EmitOptions::parse("llvm").is_err());
+    }
+
+    #[test]
+    fn parse_opt_level_test() {
+        assert_eq!(OptLevel::parse("none"), Ok(OptLevel::None));
+        assert_eq!(OptLevel::parse("speed"), Ok(OptLevel::Speed));
+        assert_eq!(
+            OptLevel::parse("speed_and_size"),
+            Ok(OptLevel::SpeedAndSize)
+        );
+        assert!(OptLevel::parse("fast").is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use crate::module_ctx::{self, ModuleContext, Settings};
+use cranelift_module::default_libcall_names;
+use cranelift_object::{ObjectBuilder, ObjectModule};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::{self, Command},
+};
+
+const RUNTIME_SOURCE: &str = include_str!("runtime.c");
+
+pub type ObjectContext = ModuleContext<ObjectModule>;
+
+impl ObjectContext {
+    // 実行ファイルを PIE としてリンクする場合は、settings.is_pic を有効にする必要がある
+    pub fn new(name: &str, settings: &Settings) -> Self {
+        let isa = module_ctx::native_isa(settings).unwrap_or_else(|msg| panic!("{msg}"));
+        let object_builder = ObjectBuilder::new(isa, name, default_libcall_names()).unwrap();
+        Self::with_module(ObjectModule::new(object_builder))
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.module.finish().emit().unwrap()
+    }
+}
+
+// オブジェクトファイルとランタイムを静的にリンクして実行ファイルを作る
+// リンカには環境変数 `CC` で指定された C コンパイラ（既定は `cc`）を使う
+pub fn link_executable(object: &[u8], output: &Path) -> io::Result<()> {
+    let work_dir = env::temp_dir().join(format!("simplelang-build-{}", process::id()));
+    fs::create_dir_all(&work_dir)?;
+    let result = link_in(&work_dir, object, output);
+    fs::remove_dir_all(&work_dir)?;
+    result
+}
+
+fn link_in(work_dir: &Path, object: &[u8], output: &Path) -> io::Result<()> {
+    let object_path: PathBuf = work_dir.join("program.o");
+    let runtime_path: PathBuf = work_dir.join("runtime.c");
+    fs::write(&object_path, object)?;
+    fs::write(&runtime_path, RUNTIME_SOURCE)?;
+
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_owned());
+    let status = Command::new(&cc)
+        .arg("-static")
+        .arg("-o")
+        .arg(output)
+        .arg(&object_path)
+        .arg(&runtime_path)
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("`{cc}` failed with {status}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ObjectContext;
+    use crate::{codegen::Codegen, module_ctx::Settings, parser};
+
+    #[test]
+    fn emit_object_test() {
+        let stmts = parser::parse_program("a = 1\nif a == 1 then print a\n").unwrap();
+
+        let mut object_ctx = ObjectContext::new("test", &Settings::default());
+        let mut codegen = Codegen::new(&mut object_ctx);
+        let func_ids: Vec<_> = stmts
+            .iter()
+            .map(|stmt| codegen.codegen_stmt(stmt).unwrap())
+            .collect();
+        codegen.codegen_main(&func_ids).unwrap();
+
+        let object = object_ctx.finish();
+        assert!(!object.is_empty());
+    }
+}
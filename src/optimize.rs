@@ -0,0 +1,176 @@
+use crate::{expr::Expression, stmt::Statement};
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    // 条件式の値が常に同じ `if` 文。条件式は畳み込み前のもの
+    ConstantCondition(Expression, bool),
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ConstantCondition(cond, true) => {
+                write!(f, "Warning: `if` condition `{cond}` is always true")
+            }
+            Self::ConstantCondition(cond, false) => write!(
+                f,
+                "Warning: `if` condition `{cond}` is always false, so its body is never executed"
+            ),
+        }
+    }
+}
+
+// 型検査を終えた文を最適化する。実行しても何も起きない文は None になる
+// - 定数だけからなる部分式を畳み込む（加減算は実行時と同じく 2^32 を法として折り返す）
+// - 条件式が定数の `if` 文を、本体だけか、何もしない文に置き換える
+pub fn optimize_stmt(stmt: &Statement, warnings: &mut Vec<Warning>) -> Option<Statement> {
+    match stmt {
+        Statement::Assign(ident, expr) => Some(Statement::Assign(ident.clone(), fold_expr(expr))),
+        Statement::Print(expr) => Some(Statement::Print(fold_expr(expr))),
+        Statement::Eval(expr) => has_call(expr).then(|| Statement::Eval(fold_expr(expr))),
+        Statement::If(cond, body_stmt) => {
+            let folded_cond = fold_expr(cond);
+            match const_condition(&folded_cond) {
+                Some(value) => {
+                    warnings.push(Warning::ConstantCondition(cond.clone(), value));
+                    if value {
+                        optimize_stmt(body_stmt, warnings)
+                    } else {
+                        None
+                    }
+                }
+                // 本体が消えた場合、条件式の関数呼び出しだけを残す
+                // 関数を呼び出さない条件式には副作用がないため、文全体を消せる
+                None => match optimize_stmt(body_stmt, warnings) {
+                    Some(body_stmt) => Some(Statement::If(folded_cond, Box::new(body_stmt))),
+                    None if has_call(&folded_cond) => Some(Statement::Eval(folded_cond)),
+                    None => None,
+                },
+            }
+        }
+    }
+}
+
+pub fn fold_expr(expr: &Expression) -> Expression {
+    match expr {
+        Expression::Identifier(_) | Expression::Number(_) => expr.clone(),
+        Expression::Add(left, right) => match (fold_expr(left), fold_expr(right)) {
+            (Expression::Number(left), Expression::Number(right)) => {
+                Expression::Number(u32::from(left).wrapping_add(right.into()).into())
+            }
+            (left, right) => Expression::Add(Box::new(left), Box::new(right)),
+        },
+        Expression::Sub(left, right) => match (fold_expr(left), fold_expr(right)) {
+            (Expression::Number(left), Expression::Number(right)) => {
+                Expression::Number(u32::from(left).wrapping_sub(right.into()).into())
+            }
+            (left, right) => Expression::Sub(Box::new(left), Box::new(right)),
+        },
+        // 真偽値のリテラルはないため、比較式は両辺を畳み込むだけにとどめる
+        Expression::Comp(left, right) => {
+            Expression::Comp(Box::new(fold_expr(left)), Box::new(fold_expr(right)))
+        }
+        Expression::Call(func_name, args) => {
+            Expression::Call(func_name.clone(), args.iter().map(fold_expr).collect())
+        }
+    }
+}
+
+// 式がホストの関数を呼び出すか
+fn has_call(expr: &Expression) -> bool {
+    match expr {
+        Expression::Identifier(_) | Expression::Number(_) => false,
+        Expression::Add(left, right)
+        | Expression::Sub(left, right)
+        | Expression::Comp(left, right) => has_call(left) || has_call(right),
+        Expression::Call(_, _) => true,
+    }
+}
+
+// 畳み込み済みの条件式が定数なら、その値を返す
+fn const_condition(cond: &Expression) -> Option<bool> {
+    match cond {
+        Expression::Comp(left, right) => match (left.as_ref(), right.as_ref()) {
+            (Expression::Number(left), Expression::Number(right)) => Some(left == right),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fold_expr, optimize_stmt, Warning};
+    use crate::{expr::*, parser, stmt::*};
+
+    fn optimize(source: &str) -> (Option<Statement>, Vec<Warning>) {
+        let mut warnings = Vec::new();
+        let stmt = optimize_stmt(&parser::parse(source).unwrap(), &mut warnings);
+        (stmt, warnings)
+    }
+
+    #[test]
+    fn fold_expr_test() {
+        let stmt = parser::parse("print a + (1 + 2 - 3)\n").unwrap();
+        let Statement::Print(expr) = stmt else {
+            unreachable!()
+        };
+        let expected = Expression::Add(
+            Box::new(Expression::Identifier("a".to_owned().into())),
+            Box::new(Expression::Number(0u32.into())),
+        );
+        assert_eq!(fold_expr(&expr), expected);
+
+        // 実行時と同じく折り返す
+        let (stmt, _) = optimize("print 0 - 1\n");
+        let expected = Statement::Print(Expression::Number(u32::MAX.into()));
+        assert_eq!(stmt, Some(expected));
+
+        let (stmt, _) = optimize("print 4294967295 + 2\n");
+        let expected = Statement::Print(Expression::Number(1u32.into()));
+        assert_eq!(stmt, Some(expected));
+    }
+
+    #[test]
+    fn constant_condition_test() {
+        let (stmt, warnings) = optimize("if 1 == 2 then print 3\n");
+        assert_eq!(stmt, None);
+        assert_eq!(
+            warnings[0].to_string(),
+            "Warning: `if` condition `1 == 2` is always false, so its body is never executed"
+        );
+
+        let (stmt, warnings) = optimize("if 1 + 1 == 2 then print 3\n");
+        let expected = Statement::Print(Expression::Number(3u32.into()));
+        assert_eq!(stmt, Some(expected));
+        let cond = Expression::Comp(
+            Box::new(Expression::Add(
+                Box::new(Expression::Number(1u32.into())),
+                Box::new(Expression::Number(1u32.into())),
+            )),
+            Box::new(Expression::Number(2u32.into())),
+        );
+        assert_eq!(warnings, vec![Warning::ConstantCondition(cond, true)]);
+
+        let (stmt, warnings) = optimize("if a == 1 then if 1 == 0 then print 3\n");
+        assert_eq!(stmt, None);
+        assert_eq!(warnings.len(), 1);
+
+        // 条件式の関数呼び出しは残す
+        let (stmt, warnings) = optimize("if f(a) == 1 + 1 then if 1 == 0 then print 3\n");
+        let expected = Statement::Eval(Expression::Comp(
+            Box::new(Expression::Call(
+                "f".to_owned().into(),
+                vec![Expression::Identifier("a".to_owned().into())],
+            )),
+            Box::new(Expression::Number(2u32.into())),
+        ));
+        assert_eq!(stmt, Some(expected));
+        assert_eq!(warnings.len(), 1);
+
+        let (stmt, warnings) = optimize("if a == 1 - 1 then print 3\n");
+        assert!(matches!(stmt, Some(Statement::If(_, _))));
+        assert!(warnings.is_empty());
+    }
+}
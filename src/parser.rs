@@ -1,99 +1,430 @@
-use super::{expr::*, stmt::*};
-use nom::{
-    branch::alt,
-    bytes::complete::tag,
-    character::complete::{alpha1, alphanumeric0, char, newline, one_of, space0, space1, u32},
-    combinator::map,
-    error::Error as NomError,
-    multi::many0,
-    sequence::tuple,
-    Err as NomErr, IResult,
+use crate::{
+    expr::*,
+    lexer::{self, Pos, Token, TokenKind},
+    stmt::*,
 };
+use std::fmt::{self, Display, Formatter};
 
-pub type Error<I> = NomErr<NomError<I>>;
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    UnexpectedChar(char),
+    NumberTooLarge(String),
+    UnexpectedToken {
+        found: TokenKind,
+        expected: &'static str,
+    },
+    ChainedComparison,
+    TooDeeplyNested,
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedChar(c) => write!(f, "unexpected character `{c}`"),
+            Self::NumberTooLarge(digits) => write!(f, "number `{digits}` is too large"),
+            Self::UnexpectedToken { found, expected } => {
+                write!(f, "expected {expected}, found {found}")
+            }
+            Self::ChainedComparison => write!(f, "comparison operators cannot be chained"),
+            Self::TooDeeplyNested => write!(
+                f,
+                "expression or statement is nested more than {MAX_NESTING_DEPTH} levels deep"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub pos: Pos,
+    pub kind: ErrorKind,
+}
+
+impl Error {
+    // 入力の途中で終わっているために失敗したか。REPL で続きの行を読むかどうかの判定に使う
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self.kind,
+            ErrorKind::UnexpectedToken {
+                found: TokenKind::Eof,
+                ..
+            }
+        )
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Parse error at {}: {}", self.pos, self.kind)
+    }
+}
+
+// ソースコードの一行。整形時にコメントと空行を保持するために使う
+#[derive(Debug, PartialEq)]
+pub enum Line {
+    Blank,
+    Comment(String),
+    Statement(Statement, Option<String>),
+}
+
+// REPL への入力。文か、値を表示する式のどちらか
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Statement(Statement),
+    Expression(Expression),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Assoc {
+    Left,
+    // `a == b == c` のような連続を許さない
+    None,
+}
 
-fn identifier(s: &str) -> IResult<&str, Identifier> {
-    let (rest, s0) = alpha1(s)?;
-    map(alphanumeric0, |s1| (s0.to_owned() + s1).into())(rest)
+struct InfixOp {
+    token: TokenKind,
+    precedence: u8,
+    assoc: Assoc,
+    build: fn(Box<Expression>, Box<Expression>) -> Expression,
 }
 
-fn number(s: &str) -> IResult<&str, Number> {
-    map(u32, |n| n.into())(s)
+// 二項演算子の表。precedence が大きいほど強く結合する
+const INFIX_OPS: &[InfixOp] = &[
+    InfixOp {
+        token: TokenKind::EqEq,
+        precedence: 1,
+        assoc: Assoc::None,
+        build: Expression::Comp,
+    },
+    InfixOp {
+        token: TokenKind::Plus,
+        precedence: 2,
+        assoc: Assoc::Left,
+        build: Expression::Add,
+    },
+    InfixOp {
+        token: TokenKind::Minus,
+        precedence: 2,
+        assoc: Assoc::Left,
+        build: Expression::Sub,
+    },
+];
+
+fn infix_op(kind: &TokenKind) -> Option<&'static InfixOp> {
+    INFIX_OPS.iter().find(|op| op.token == *kind)
 }
 
-fn term(s: &str) -> IResult<&str, Expression> {
-    alt((
-        map(identifier, |ident| Expression::Identifier(ident)),
-        map(number, |num| Expression::Number(num)),
-        map(
-            tuple((char('('), space0, expression, space0, char(')'))),
-            |(_, _, expr, _, _)| expr,
-        ),
-    ))(s)
+// 構文木の深さの上限。型検査やコード生成は構文木を再帰でたどるため、
+// 深すぎる入力でスタックが溢れないように解析の段階で拒否する
+const MAX_NESTING_DEPTH: usize = 256;
+
+struct Parser {
+    tokens: Vec<Token>,
+    index: usize,
+    // 開いている括弧の数
+    paren_depth: usize,
+    // 括弧、二項演算子、`if` の本体による入れ子の深さ
+    nesting_depth: usize,
+    // 文の途中の改行の前で読み飛ばしたコメント
+    nested_comments: Vec<String>,
 }
 
-fn addsub(s: &str) -> IResult<&str, Expression> {
-    map(
-        tuple((term, many0(tuple((space0, one_of("+-"), space0, term))))),
-        |(t0, rest)| {
-            let mut expr = t0;
-            for (_, op, _, t1) in rest.into_iter() {
-                match op {
-                    '+' => expr = Expression::Add(Box::new(expr), Box::new(t1)),
-                    '-' => expr = Expression::Sub(Box::new(expr), Box::new(t1)),
-                    _ => unreachable!(),
+impl Parser {
+    fn new(source: &str) -> Result<Self, Error> {
+        Ok(Self {
+            tokens: lexer::tokenize(source)?,
+            index: 0,
+            paren_depth: 0,
+            nesting_depth: 0,
+            nested_comments: Vec::new(),
+        })
+    }
+
+    // 入れ子を一段深くする。戻るときは呼び出し側で nesting_depth を元に戻す
+    fn nest(&mut self) -> Result<(), Error> {
+        self.nesting_depth += 1;
+        if self.nesting_depth > MAX_NESTING_DEPTH {
+            return Err(Error {
+                pos: self.peek().pos,
+                kind: ErrorKind::TooDeeplyNested,
+            });
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.index]
+    }
+
+    // トークン列の最後の `Eof` より先には進まない
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.index].clone();
+        if token.kind != TokenKind::Eof {
+            self.index += 1;
+        }
+        token
+    }
+
+    fn unexpected(&self, expected: &'static str) -> Error {
+        let token = self.peek();
+        Error {
+            pos: token.pos,
+            kind: ErrorKind::UnexpectedToken {
+                found: token.kind.clone(),
+                expected,
+            },
+        }
+    }
+
+    fn expect(&mut self, kind: TokenKind, expected: &'static str) -> Result<Token, Error> {
+        if self.peek().kind == kind {
+            Ok(self.advance())
+        } else {
+            Err(self.unexpected(expected))
+        }
+    }
+
+    // 文の途中の改行を読み飛ばす。改行の前のコメントは、整形で失わないように取っておく
+    fn skip_newlines(&mut self) {
+        loop {
+            match &self.peek().kind {
+                TokenKind::Newline => {}
+                TokenKind::Comment(comment) => self.nested_comments.push(comment.clone()),
+                _ => break,
+            }
+            self.advance();
+        }
+    }
+
+    // 括弧の中では改行を読み飛ばし、式を複数行に分けて書けるようにする
+    fn skip_nested_newlines(&mut self) {
+        if self.paren_depth > 0 {
+            self.skip_newlines();
+        }
+    }
+
+    fn skip_blank_lines(&mut self) {
+        while matches!(self.peek().kind, TokenKind::Newline | TokenKind::Comment(_)) {
+            self.advance();
+        }
+    }
+
+    // 行末のコメントを読み、改行または入力の終端を受け付ける
+    fn end_of_line(&mut self) -> Result<Option<String>, Error> {
+        let comment = match &self.peek().kind {
+            TokenKind::Comment(comment) => {
+                let comment = comment.clone();
+                self.advance();
+                Some(comment)
+            }
+            _ => None,
+        };
+        match self.peek().kind {
+            TokenKind::Newline => {
+                self.advance();
+                Ok(comment)
+            }
+            TokenKind::Eof => Ok(comment),
+            _ => Err(self.unexpected("end of line")),
+        }
+    }
+
+    fn line(&mut self) -> Result<Line, Error> {
+        match self.peek().kind {
+            TokenKind::Newline => {
+                self.advance();
+                Ok(Line::Blank)
+            }
+            TokenKind::Comment(_) => Ok(Line::Comment(self.end_of_line()?.unwrap())),
+            _ => {
+                let stmt = self.statement()?;
+                // 文の途中のコメントは、行末のコメントとまとめて文の後に置く
+                let mut comments = std::mem::take(&mut self.nested_comments);
+                comments.extend(self.end_of_line()?);
+                let comment = (!comments.is_empty()).then(|| comments.join(" "));
+                Ok(Line::Statement(stmt, comment))
+            }
+        }
+    }
+
+    // 次が文の始まり（`if`、`print` または `識別子 =`）か
+    fn at_statement(&self) -> bool {
+        match self.peek().kind {
+            TokenKind::If | TokenKind::Print => true,
+            TokenKind::Identifier(_) => self
+                .tokens
+                .get(self.index + 1)
+                .is_some_and(|token| token.kind == TokenKind::Assign),
+            _ => false,
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, Error> {
+        match self.peek().kind.clone() {
+            TokenKind::Identifier(name) => {
+                self.advance();
+                self.expect(TokenKind::Assign, "`=`")?;
+                let expr = self.expression(0)?;
+                Ok(Statement::Assign(name.into(), expr))
+            }
+            TokenKind::If => {
+                self.advance();
+                let cond = self.expression(0)?;
+                self.expect(TokenKind::Then, "`then`")?;
+                // `then` の後で改行して、本体を次の行に書ける
+                self.skip_newlines();
+                self.nest()?;
+                let body_stmt = self.statement()?;
+                self.nesting_depth -= 1;
+                Ok(Statement::If(cond, Box::new(body_stmt)))
+            }
+            TokenKind::Print => {
+                self.advance();
+                Ok(Statement::Print(self.expression(0)?))
+            }
+            _ => Err(self.unexpected("statement")),
+        }
+    }
+
+    // 優先順位法（Pratt parsing）で、結合の強さが min_precedence 以上の演算子までを読む
+    fn expression(&mut self, min_precedence: u8) -> Result<Expression, Error> {
+        let nesting_depth = self.nesting_depth;
+        let mut lhs = self.term()?;
+        let mut non_assoc_precedence = None;
+        loop {
+            self.skip_nested_newlines();
+            let Some(op) = infix_op(&self.peek().kind) else {
+                break;
+            };
+            if op.precedence < min_precedence {
+                break;
+            }
+            if non_assoc_precedence == Some(op.precedence) {
+                return Err(Error {
+                    pos: self.peek().pos,
+                    kind: ErrorKind::ChainedComparison,
+                });
+            }
+            self.advance();
+            // `1 + 1 + ...` のような左結合の連続も、演算子ごとに構文木が一段深くなる
+            self.nest()?;
+            let rhs = self.expression(op.precedence + 1)?;
+            lhs = (op.build)(Box::new(lhs), Box::new(rhs));
+            non_assoc_precedence = (op.assoc == Assoc::None).then_some(op.precedence);
+        }
+        self.nesting_depth = nesting_depth;
+        Ok(lhs)
+    }
+
+    // 関数呼び出しの `(` から `)` までを読み、引数の式を返す
+    fn arguments(&mut self) -> Result<Vec<Expression>, Error> {
+        self.expect(TokenKind::LParen, "`(`")?;
+        self.paren_depth += 1;
+        self.nest()?;
+        let mut args = Vec::new();
+        self.skip_nested_newlines();
+        if self.peek().kind != TokenKind::RParen {
+            loop {
+                args.push(self.expression(0)?);
+                self.skip_nested_newlines();
+                if self.peek().kind != TokenKind::Comma {
+                    break;
                 }
+                self.advance();
             }
-            expr
-        },
-    )(s)
-}
-
-fn expression(s: &str) -> IResult<&str, Expression> {
-    alt((
-        map(
-            tuple((addsub, space0, tag("=="), space0, addsub)),
-            |(a1, _, _, _, a2)| Expression::Comp(Box::new(a1), Box::new(a2)),
-        ),
-        addsub,
-    ))(s)
-}
-
-fn statement(s: &str) -> IResult<&str, Statement> {
-    alt((
-        map(
-            tuple((identifier, space0, char('='), space0, expression, newline)),
-            |(ident, _, _, _, expr, _)| Statement::Assign(ident, expr),
-        ),
-        map(
-            tuple((
-                tag("if"),
-                space1,
-                expression,
-                space1,
-                tag("then"),
-                space1,
-                statement,
-            )),
-            |(_, _, expr, _, _, _, stmt)| Statement::If(expr, Box::new(stmt)),
-        ),
-        map(
-            tuple((tag("print"), space1, expression, newline)),
-            |(_, _, expr, _)| Statement::Print(expr),
-        ),
-    ))(s)
+        }
+        self.expect(TokenKind::RParen, "`,` or `)`")?;
+        self.paren_depth -= 1;
+        self.nesting_depth -= 1;
+        Ok(args)
+    }
+
+    fn term(&mut self) -> Result<Expression, Error> {
+        self.skip_nested_newlines();
+        match self.peek().kind.clone() {
+            TokenKind::Identifier(name) => {
+                self.advance();
+                if self.peek().kind == TokenKind::LParen {
+                    let args = self.arguments()?;
+                    return Ok(Expression::Call(name.into(), args));
+                }
+                Ok(Expression::Identifier(name.into()))
+            }
+            TokenKind::Number(num) => {
+                self.advance();
+                Ok(Expression::Number(num.into()))
+            }
+            TokenKind::LParen => {
+                self.advance();
+                self.paren_depth += 1;
+                self.nest()?;
+                let expr = self.expression(0)?;
+                self.expect(TokenKind::RParen, "`)`")?;
+                self.paren_depth -= 1;
+                self.nesting_depth -= 1;
+                Ok(expr)
+            }
+            _ => Err(self.unexpected("expression")),
+        }
+    }
 }
 
-pub fn parse(s: &str) -> Result<Statement, Error<&str>> {
-    match statement(s) {
-        Ok((_, stmt)) => Ok(stmt),
-        Err(err) => Err(err),
+// 先頭の空行とコメントを読み飛ばし、最初の一文を解析する
+pub fn parse(s: &str) -> Result<Statement, Error> {
+    let mut parser = Parser::new(s)?;
+    parser.skip_blank_lines();
+    let stmt = parser.statement()?;
+    parser.end_of_line()?;
+    Ok(stmt)
+}
+
+// 1 行の式を解析する
+pub fn parse_expr(s: &str) -> Result<Expression, Error> {
+    let mut parser = Parser::new(s)?;
+    parser.skip_blank_lines();
+    let expr = parser.expression(0)?;
+    parser.end_of_line()?;
+    Ok(expr)
+}
+
+// REPL への一つの入力を、文または式として解析する。後に続く文があればエラーにする
+pub fn parse_input(s: &str) -> Result<Input, Error> {
+    let mut parser = Parser::new(s)?;
+    parser.skip_blank_lines();
+    let input = if parser.at_statement() {
+        Input::Statement(parser.statement()?)
+    } else {
+        Input::Expression(parser.expression(0)?)
+    };
+    parser.end_of_line()?;
+    parser.skip_blank_lines();
+    parser.expect(TokenKind::Eof, "end of input")?;
+    Ok(input)
+}
+
+pub fn parse_lines(s: &str) -> Result<Vec<Line>, Error> {
+    let mut parser = Parser::new(s)?;
+    let mut lines = Vec::new();
+    while parser.peek().kind != TokenKind::Eof {
+        lines.push(parser.line()?);
     }
+    Ok(lines)
+}
+
+pub fn parse_program(s: &str) -> Result<Vec<Statement>, Error> {
+    Ok(parse_lines(s)?
+        .into_iter()
+        .filter_map(|line| match line {
+            Line::Statement(stmt, _) => Some(stmt),
+            _ => None,
+        })
+        .collect())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::parse;
+    use super::{parse, parse_expr, parse_input, parse_program, ErrorKind, Input};
+    use crate::lexer::TokenKind;
     use crate::{expr::*, stmt::*};
 
     #[test]
@@ -169,4 +500,249 @@ mod tests {
         ));
         assert_eq!(stmt, expected, "expected {expected:?}, but got {stmt:?}");
     }
+
+    #[test]
+    fn parse_comment_and_whitespace_test() {
+        let stmt = parse("# comment\n\n  print 1\t# trailing comment  \r\n").unwrap();
+
+        let expected = Statement::Print(Expression::Number(1u32.into()));
+        assert_eq!(stmt, expected, "expected {expected:?}, but got {stmt:?}");
+
+        let stmt = parse("a = 1   ").unwrap();
+
+        let expected = Statement::Assign("a".to_owned().into(), Expression::Number(1u32.into()));
+        assert_eq!(stmt, expected, "expected {expected:?}, but got {stmt:?}");
+    }
+
+    #[test]
+    fn parse_program_test() {
+        let stmts = parse_program(
+            "# header\r\na = 1\r\n\r\n\tif a == 1 then print a # print it\r\n  \n# footer",
+        )
+        .unwrap();
+
+        let expected = vec![
+            Statement::Assign("a".to_owned().into(), Expression::Number(1u32.into())),
+            Statement::If(
+                Expression::Comp(
+                    Box::new(Expression::Identifier("a".to_owned().into())),
+                    Box::new(Expression::Number(1u32.into())),
+                ),
+                Box::new(Statement::Print(Expression::Identifier(
+                    "a".to_owned().into(),
+                ))),
+            ),
+        ];
+        assert_eq!(stmts, expected, "expected {expected:?}, but got {stmts:?}");
+
+        assert!(parse_program("").unwrap().is_empty());
+        assert!(parse_program("print 1 print 2\n").is_err());
+    }
+
+    #[test]
+    fn parse_keyword_as_identifier_test() {
+        let err = parse("print = 3\n").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::UnexpectedToken {
+                found: TokenKind::Assign,
+                expected: "expression"
+            }
+        );
+
+        let err = parse("if = 3\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Parse error at line 1, column 4: expected expression, found `=`"
+        );
+    }
+
+    #[test]
+    fn parse_multi_line_statement_test() {
+        let stmts = parse_program("if a == (1\n  + 2\n) then\n\n  print a\nprint 3\n").unwrap();
+        let expected = vec![
+            Statement::If(
+                Expression::Comp(
+                    Box::new(Expression::Identifier("a".to_owned().into())),
+                    Box::new(Expression::Add(
+                        Box::new(Expression::Number(1u32.into())),
+                        Box::new(Expression::Number(2u32.into())),
+                    )),
+                ),
+                Box::new(Statement::Print(Expression::Identifier(
+                    "a".to_owned().into(),
+                ))),
+            ),
+            Statement::Print(Expression::Number(3u32.into())),
+        ];
+        assert_eq!(stmts, expected, "expected {expected:?}, but got {stmts:?}");
+
+        // 括弧の外では改行で文が終わる
+        assert!(parse_program("print 1\n+ 2").is_err());
+    }
+
+    #[test]
+    fn parse_comment_in_multi_line_statement_test() {
+        assert_eq!(
+            parse_program("if a then # c\n  print 1\n").unwrap(),
+            parse_program("if a then print 1").unwrap()
+        );
+        assert_eq!(
+            parse_program("a = (1 + # x\n  # y\n  2)\n").unwrap(),
+            parse_program("a = (1 + 2)").unwrap()
+        );
+        assert!(parse_input("if a then # c\n").unwrap_err().is_incomplete());
+        assert!(parse_input("a = (1 + # x\n").unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn incomplete_input_test() {
+        for source in [
+            "if a == 1 then\n",
+            "print (1 +\n",
+            "print ((1)\n",
+            "a = (\n2",
+        ] {
+            let err = parse(source).unwrap_err();
+            assert!(err.is_incomplete(), "{source:?}: {err}");
+        }
+        for source in ["a =\n", "print 1 +\n", "print )\n", "if a then print 1)"] {
+            let err = parse(source).unwrap_err();
+            assert!(!err.is_incomplete(), "{source:?}: {err}");
+        }
+    }
+
+    #[test]
+    fn parse_expr_test() {
+        let expr = parse_expr("a + 1 == 2  # comment").unwrap();
+        let expected = Expression::Comp(
+            Box::new(Expression::Add(
+                Box::new(Expression::Identifier("a".to_owned().into())),
+                Box::new(Expression::Number(1u32.into())),
+            )),
+            Box::new(Expression::Number(2u32.into())),
+        );
+        assert_eq!(expr, expected, "expected {expected:?}, but got {expr:?}");
+
+        let err = parse_expr("a = 1").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::UnexpectedToken {
+                found: TokenKind::Assign,
+                expected: "end of line"
+            }
+        );
+    }
+
+    #[test]
+    fn parse_input_test() {
+        let a = || Expression::Identifier("a".to_owned().into());
+        assert_eq!(
+            parse_input("a = 1").unwrap(),
+            Input::Statement(Statement::Assign(
+                "a".to_owned().into(),
+                Expression::Number(1u32.into())
+            ))
+        );
+        assert_eq!(parse_input("a").unwrap(), Input::Expression(a()));
+        assert_eq!(
+            parse_input("a == 1").unwrap(),
+            Input::Expression(Expression::Comp(
+                Box::new(a()),
+                Box::new(Expression::Number(1u32.into()))
+            ))
+        );
+        assert!(parse_input("(a +\n").unwrap_err().is_incomplete());
+        assert!(parse_input("a b").is_err());
+        assert!(parse_input("a = 1\n# comment\n").is_ok());
+        assert!(!parse_input("a = 1\nb").unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn parse_chained_comparison_test() {
+        let err = parse("print 1 == 2 == 3\n").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ChainedComparison);
+
+        let stmt = parse("print (1 == 2) == 3\n").unwrap();
+        let expected = Statement::Print(Expression::Comp(
+            Box::new(Expression::Comp(
+                Box::new(Expression::Number(1u32.into())),
+                Box::new(Expression::Number(2u32.into())),
+            )),
+            Box::new(Expression::Number(3u32.into())),
+        ));
+        assert_eq!(stmt, expected, "expected {expected:?}, but got {stmt:?}");
+    }
+
+    #[test]
+    fn parse_precedence_test() {
+        let stmt = parse("if 1 + 2 == 3 - a then print 1\n").unwrap();
+
+        let expected = Statement::If(
+            Expression::Comp(
+                Box::new(Expression::Add(
+                    Box::new(Expression::Number(1u32.into())),
+                    Box::new(Expression::Number(2u32.into())),
+                )),
+                Box::new(Expression::Sub(
+                    Box::new(Expression::Number(3u32.into())),
+                    Box::new(Expression::Identifier("a".to_owned().into())),
+                )),
+            ),
+            Box::new(Statement::Print(Expression::Number(1u32.into()))),
+        );
+        assert_eq!(stmt, expected, "expected {expected:?}, but got {stmt:?}");
+    }
+
+    #[test]
+    fn parse_call_test() {
+        let expr = parse_expr("f(a, 1 + 2) + g()").unwrap();
+        let expected = Expression::Add(
+            Box::new(Expression::Call(
+                "f".to_owned().into(),
+                vec![
+                    Expression::Identifier("a".to_owned().into()),
+                    Expression::Add(
+                        Box::new(Expression::Number(1u32.into())),
+                        Box::new(Expression::Number(2u32.into())),
+                    ),
+                ],
+            )),
+            Box::new(Expression::Call("g".to_owned().into(), vec![])),
+        );
+        assert_eq!(expr, expected, "expected {expected:?}, but got {expr:?}");
+
+        assert_eq!(
+            parse_expr("f(\n  a,\n  b\n)").unwrap().to_string(),
+            "f(a, b)"
+        );
+        assert!(parse_input("f(a,\n").unwrap_err().is_incomplete());
+        assert!(parse_expr("f(a b)").is_err());
+        assert!(parse_expr("f(a,)").is_err());
+    }
+
+    #[test]
+    fn parse_nesting_depth_test() {
+        let parens = |n| format!("{}1{}", "(".repeat(n), ")".repeat(n));
+        let chain = |n| format!("1{}", " + 1".repeat(n));
+        let calls = |n| format!("{}1{}", "f(".repeat(n), ")".repeat(n));
+        let ifs = |n| format!("{}print 1", "if a then ".repeat(n));
+        for (source, limit) in [
+            (parens(256), parens(257)),
+            (chain(256), chain(257)),
+            (calls(256), calls(257)),
+        ] {
+            assert!(parse_expr(&source).is_ok());
+            let err = parse_expr(&limit).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::TooDeeplyNested, "{err}");
+            assert!(!err.is_incomplete());
+        }
+        assert!(parse(&ifs(256)).is_ok());
+        assert_eq!(
+            parse(&ifs(257)).unwrap_err().kind,
+            ErrorKind::TooDeeplyNested
+        );
+        // 入れ子でなければ、いくつ並べてもよい
+        assert!(parse_expr(&format!("{} + 1", parens(256))).is_ok());
+    }
 }
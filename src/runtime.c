@@ -0,0 +1,8 @@
+/* `simplelang build` で生成した実行ファイルにリンクするランタイム */
+#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+
+void println_u32(uint32_t n) {
+    printf("%" PRIu32 "\n", n);
+}
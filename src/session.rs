@@ -0,0 +1,782 @@
+use crate::{
+    codegen::Codegen,
+    env::{self, Env},
+    expr::Expression,
+    host::HostFunction,
+    interp::{Interpreter, Value},
+    jit_ctx::{self, JITContext, JITContextBuilder},
+    module_ctx::EmitOptions,
+    optimize::{self, Warning},
+    parser::{self, Input},
+    session_file::Variable,
+    stmt::Statement,
+    type_::TypeRepr,
+};
+use cranelift_module::ModuleError;
+use std::{
+    fmt::{self, Display, Formatter},
+    io, mem,
+};
+
+#[derive(Debug)]
+pub enum Error {
+    Parse(parser::Error),
+    // 型エラー、未定義の変数、未代入の変数の読み出し
+    Type(env::Error),
+    // `print` の出力の失敗
+    Runtime(io::Error),
+    // Cranelift でのモジュールの作成やコンパイルの失敗
+    Compile(String),
+    // restore_variables に渡した変数の型と値が一致しない
+    InvalidVariable(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(err) => write!(f, "{err}"),
+            Self::Type(err) => write!(f, "{err}"),
+            Self::Runtime(err) => write!(f, "{err}"),
+            Self::Compile(msg) => write!(f, "Compile error: {msg}"),
+            Self::InvalidVariable(msg) => write!(f, "Invalid variable: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<parser::Error> for Error {
+    fn from(err: parser::Error) -> Self {
+        Self::Parse(err)
+    }
+}
+
+impl From<env::Error> for Error {
+    fn from(err: env::Error) -> Self {
+        Self::Type(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Runtime(err)
+    }
+}
+
+impl From<Box<ModuleError>> for Error {
+    fn from(err: Box<ModuleError>) -> Self {
+        Self::Compile(err.to_string())
+    }
+}
+
+// セッションを作り直す際に、同じ設定のバックエンドを作るための設定
+// ホストの関数は、JIT では builder が、インタプリタではここで持つ
+#[derive(Debug, Clone)]
+enum BackendConfig {
+    Jit(JITContextBuilder),
+    Interp(Vec<HostFunction>),
+}
+
+impl BackendConfig {
+    fn build(&self) -> Result<Backend, Error> {
+        match self {
+            Self::Jit(builder) => {
+                let jit_ctx = builder.clone().try_build().map_err(Error::Compile)?;
+                Ok(Backend::Jit(Box::new(jit_ctx)))
+            }
+            Self::Interp(functions) => {
+                let mut interp = Interpreter::new();
+                for func in functions {
+                    interp.add_function(func);
+                }
+                Ok(Backend::Interp(interp))
+            }
+        }
+    }
+
+    fn functions(&self) -> &[HostFunction] {
+        match self {
+            Self::Jit(builder) => &builder.functions,
+            Self::Interp(functions) => functions,
+        }
+    }
+
+    fn add_function(&mut self, func: HostFunction) {
+        match self {
+            Self::Jit(builder) => *builder = builder.clone().function(func),
+            Self::Interp(functions) => {
+                functions.retain(|other| other.name != func.name);
+                functions.push(func);
+            }
+        }
+    }
+}
+
+// 型検査を終えた文を実行するバックエンド
+enum Backend {
+    Jit(Box<JITContext>),
+    Interp(Interpreter),
+}
+
+impl Backend {
+    // JIT ではすべての文をコンパイルしてから、順に実行する
+    fn exec_program(&mut self, stmts: &[Statement]) -> Result<(), Error> {
+        match self {
+            Self::Jit(jit_ctx) => {
+                let func_ptrs = Codegen::new(jit_ctx).codegen_program(stmts)?;
+                let result = func_ptrs.into_iter().try_for_each(|func_ptr| {
+                    func_ptr();
+                    jit_ctx::take_print_error().map_or(Ok(()), Err)
+                });
+                compact_if_needed(jit_ctx);
+                Ok(result?)
+            }
+            Self::Interp(interp) => Ok(stmts.iter().try_for_each(|stmt| interp.exec_stmt(stmt))?),
+        }
+    }
+
+    fn eval_expr(&mut self, expr: &Expression, ty: TypeRepr) -> Result<Value, Error> {
+        match self {
+            Self::Jit(jit_ctx) => {
+                let func_ptr = Codegen::new(jit_ctx).codegen_eval(expr)?;
+                let value = value_of_type(func_ptr(), ty);
+                compact_if_needed(jit_ctx);
+                Ok(value)
+            }
+            Self::Interp(interp) => Ok(interp.eval_expr(expr)),
+        }
+    }
+
+    // JIT では真偽値も 4 バイトの整数として格納されているため、型に合わせて読み出す
+    fn variable(&mut self, name: &str, ty: TypeRepr) -> Option<Value> {
+        match self {
+            Self::Jit(jit_ctx) => jit_ctx.variable_value(name).map(|n| value_of_type(n, ty)),
+            Self::Interp(interp) => interp.variable(name),
+        }
+    }
+
+    // すべての変数の値を保存する。JIT では真偽値も整数として保存する
+    fn save_values(&mut self) -> Vec<(String, Value)> {
+        match self {
+            Self::Jit(jit_ctx) => {
+                let names: Vec<String> = jit_ctx.variable_names().map(str::to_owned).collect();
+                names
+                    .into_iter()
+                    .filter_map(|name| {
+                        let value = jit_ctx.variable_value(&name)?;
+                        Some((name, Value::Int(value)))
+                    })
+                    .collect()
+            }
+            Self::Interp(interp) => interp
+                .variable_names()
+                .map(|name| (name.to_owned(), interp.variable(name).unwrap()))
+                .collect(),
+        }
+    }
+
+    // save_values で保存した値に戻す
+    // JIT で後から定義された変数のデータは残るが、Env から消えるため参照されない
+    fn restore_values(&mut self, values: &[(String, Value)]) {
+        match self {
+            Self::Jit(jit_ctx) => {
+                let names: Vec<String> = jit_ctx.variable_names().map(str::to_owned).collect();
+                for name in names {
+                    jit_ctx.unset_variable_value(&name);
+                }
+            }
+            Self::Interp(interp) => interp.clear_variables(),
+        }
+        for (name, value) in values {
+            self.set_variable(name, *value);
+        }
+    }
+
+    fn set_variable(&mut self, name: &str, value: Value) {
+        match self {
+            Self::Jit(jit_ctx) => jit_ctx.set_variable_value(name, value.as_u32()),
+            Self::Interp(interp) => interp.set_variable(name, value),
+        }
+    }
+}
+
+// 使い終えたバックエンドを捨てる
+// JITModule は drop してもコードとデータを解放しないため、明示的に解放する
+fn free_backend(backend: Backend) {
+    if let Backend::Jit(jit_ctx) = backend {
+        // SAFETY: JIT の関数ポインタはセッションの外に渡さず、実行を終えたものしか残っていない
+        unsafe { jit_ctx.module.free_memory() };
+    }
+}
+
+// JIT のモジュールに溜まった関数がこの数に達したら、モジュールを作り直してメモリを解放する
+const JIT_COMPACT_THRESHOLD: usize = 256;
+
+fn compact_if_needed(jit_ctx: &mut JITContext) {
+    if jit_ctx.function_count() >= JIT_COMPACT_THRESHOLD {
+        // SAFETY: セッションでは、実行し終えた文や式の関数を再び呼び出すことはない
+        unsafe { jit_ctx.compact() };
+    }
+}
+
+// JIT で 4 バイトの整数として得た値を、型に合わせて解釈する
+fn value_of_type(n: u32, ty: TypeRepr) -> Value {
+    match ty {
+        TypeRepr::Bool => Value::Bool(n != 0),
+        _ => Value::Int(n),
+    }
+}
+
+// ホストの Rust プログラムに埋め込んで、入力を REPL と同じように実行するセッション
+// 変数とその型は入力をまたいで保持される。不正な入力に対しては panic せずにエラーを返す
+pub struct Session {
+    config: BackendConfig,
+    env: Env,
+    backend: Backend,
+    // 最適化の際に見つかった、まだ取り出されていない警告
+    warnings: Vec<Warning>,
+}
+
+// `Session::rollback` で戻るための、変数の型と値
+pub struct Snapshot {
+    env: Env,
+    values: Vec<(String, Value)>,
+}
+
+impl Session {
+    // JIT コンパイルして実行するセッションを作る
+    pub fn new() -> Result<Self, Error> {
+        Self::with_jit(JITContext::builder())
+    }
+
+    // builder の設定で JIT コンパイルするセッションを作る
+    pub fn with_jit(builder: JITContextBuilder) -> Result<Self, Error> {
+        Self::with_config(BackendConfig::Jit(builder))
+    }
+
+    // インタプリタで実行するセッションを作る
+    pub fn with_interpreter() -> Self {
+        Self {
+            config: BackendConfig::Interp(Vec::new()),
+            env: Env::new_empty(),
+            backend: Backend::Interp(Interpreter::new()),
+            warnings: Vec::new(),
+        }
+    }
+
+    // builder に追加済みのホストの関数も呼び出せる
+    fn with_config(config: BackendConfig) -> Result<Self, Error> {
+        let mut session = Self {
+            backend: config.build()?,
+            config,
+            env: Env::new_empty(),
+            warnings: Vec::new(),
+        };
+        session.env = session.with_functions(Env::new_empty());
+        Ok(session)
+    }
+
+    // Env に、セッションに追加したホストの関数を追加する
+    fn with_functions(&self, mut env: Env) -> Env {
+        for func in self.config.functions() {
+            env.add_function(func);
+        }
+        env
+    }
+
+    // スクリプトから呼び出せるホストの関数を追加する。同じ名前の関数があれば置き換える
+    // JIT ではモジュールを作り直すが、変数の値はそのまま引き継ぐ
+    pub fn add_function(&mut self, func: HostFunction) -> Result<(), Error> {
+        let mut config = self.config.clone();
+        config.add_function(func.clone());
+        match &mut self.backend {
+            Backend::Jit(_) => {
+                let values = self.backend.save_values();
+                let mut backend = config.build()?;
+                backend.restore_values(&values);
+                self.replace_backend(backend);
+            }
+            Backend::Interp(interp) => interp.add_function(&func),
+        }
+        self.config = config;
+        self.env.add_function(&func);
+        Ok(())
+    }
+
+    // 一つの文か式、または複数行のプログラムを実行する。式の場合はその値を返す
+    pub fn eval(&mut self, source: &str) -> Result<Option<Value>, Error> {
+        match parser::parse_input(source) {
+            Ok(input) => self.eval_input(input),
+            Err(input_err) => {
+                // 解析がより先まで進んだ方のエラーを報告する
+                let stmts = parser::parse_program(source).map_err(|program_err| {
+                    let pos = |err: &parser::Error| (err.pos.line, err.pos.column);
+                    if pos(&program_err) >= pos(&input_err) {
+                        program_err
+                    } else {
+                        input_err
+                    }
+                })?;
+                self.exec_program(&stmts)?;
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn eval_input(&mut self, input: Input) -> Result<Option<Value>, Error> {
+        match input {
+            Input::Statement(stmt) => {
+                self.exec_program(&[stmt])?;
+                Ok(None)
+            }
+            Input::Expression(expr) => Ok(Some(self.eval_expr(&expr)?)),
+        }
+    }
+
+    // 式の型を検査してから評価する
+    pub fn eval_expr(&mut self, expr: &Expression) -> Result<Value, Error> {
+        let ty = self.env.analyze_expr(expr)?;
+        self.backend.eval_expr(&optimize::fold_expr(expr), ty)
+    }
+
+    // 式を評価せずに型だけを検査する
+    pub fn type_of(&mut self, expr: &Expression) -> Result<TypeRepr, Error> {
+        Ok(self.env.analyze_expr(expr)?)
+    }
+
+    // すべての文の型検査に成功した場合だけ、最適化してから順に実行する
+    // 型検査に失敗した場合は、変数の型はどれも変わらない
+    pub fn exec_program(&mut self, stmts: &[Statement]) -> Result<(), Error> {
+        let snapshot = self.env.clone();
+        for stmt in stmts {
+            if let Err(err) = self.env.analyze_stmt(stmt) {
+                self.env = snapshot;
+                return Err(err.into());
+            }
+        }
+
+        let stmts: Vec<Statement> = stmts
+            .iter()
+            .filter_map(|stmt| optimize::optimize_stmt(stmt, &mut self.warnings))
+            .collect();
+        self.backend.exec_program(&stmts)
+    }
+
+    // これまでの最適化で見つかった警告を取り出す
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        mem::take(&mut self.warnings)
+    }
+
+    // 変数の現在の値。未定義の変数や、まだ値のない変数には None を返す
+    pub fn variable(&mut self, name: &str) -> Option<Value> {
+        let (_, ty) = self
+            .env
+            .variables()
+            .into_iter()
+            .find(|(var_name, _)| var_name == name)?;
+        self.backend.variable(name, ty)
+    }
+
+    // 変数の名前、型と現在の値を、名前の順に返す
+    pub fn variables(&mut self) -> Vec<Variable> {
+        self.env
+            .variables()
+            .into_iter()
+            .map(|(name, ty)| Variable {
+                assigned: self.env.is_assigned(&name),
+                value: self.backend.variable(&name, ty),
+                name,
+                ty,
+            })
+            .collect()
+    }
+
+    // 型検査で知られている変数の名前。補完の候補に使う
+    // 取り消した入力で JIT のモジュールに定義された変数は含めない
+    pub fn variable_names(&mut self) -> Vec<String> {
+        self.env
+            .variables()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    // 与えた変数だけを持つ新しいセッションに置き換える
+    pub fn restore_variables(&mut self, vars: &[Variable]) -> Result<(), Error> {
+        // セッションを置き換える前に検証し、失敗しても元のセッションを残す
+        for var in vars {
+            match var.value {
+                None if var.assigned => {
+                    return Err(Error::InvalidVariable(format!(
+                        "`{}` is assigned but has no value",
+                        var.name
+                    )));
+                }
+                Some(value) if value.ty() != var.ty => {
+                    return Err(Error::InvalidVariable(format!(
+                        "`{}` has type {} but its value is {}",
+                        var.name,
+                        var.ty,
+                        value.ty()
+                    )));
+                }
+                _ => {}
+            }
+        }
+        let backend = self.config.build()?;
+        self.replace_backend(backend);
+        self.env = self.with_functions(Env::from_variables(
+            vars.iter()
+                .map(|var| (var.name.clone(), var.ty, var.assigned)),
+        ));
+        for var in vars {
+            if let Some(value) = var.value {
+                self.backend.set_variable(&var.name, value);
+            }
+        }
+        Ok(())
+    }
+
+    // すべての変数を破棄する
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.restore_variables(&[])
+    }
+
+    // 現在の変数の型と値を保存する
+    pub fn snapshot(&mut self) -> Snapshot {
+        Snapshot {
+            env: self.env.clone(),
+            values: self.backend.save_values(),
+        }
+    }
+
+    // snapshot で保存した状態に戻す
+    pub fn rollback(&mut self, snapshot: Snapshot) {
+        // snapshot の後で追加した関数も呼び出せるままにする
+        self.env = self.with_functions(snapshot.env);
+        self.backend.restore_values(&snapshot.values);
+    }
+
+    // バックエンドを作り直したものに置き換える。`:ir` などで切り替えた表示の設定は引き継ぐ
+    fn replace_backend(&mut self, mut backend: Backend) {
+        if let (Some(emit), Backend::Jit(jit_ctx)) = (self.emit_options().copied(), &mut backend) {
+            jit_ctx.emit = emit;
+        }
+        free_backend(mem::replace(&mut self.backend, backend));
+    }
+
+    // 中間表現などの表示の設定。インタプリタでは None を返す
+    pub fn emit_options(&mut self) -> Option<&mut EmitOptions> {
+        match &mut self.backend {
+            Backend::Jit(jit_ctx) => Some(&mut jit_ctx.emit),
+            Backend::Interp(_) => None,
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        free_backend(mem::replace(
+            &mut self.backend,
+            Backend::Interp(Interpreter::new()),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Session};
+    use crate::{
+        host::{HostFn, HostFunction},
+        interp::Value,
+        jit_ctx::JITContext,
+        session_file::{self, Variable},
+        type_::TypeRepr,
+    };
+    use std::cell::Cell;
+
+    fn sessions() -> [Session; 2] {
+        [Session::new().unwrap(), Session::with_interpreter()]
+    }
+
+    #[test]
+    fn eval_test() {
+        for mut session in sessions() {
+            assert_eq!(session.eval("a = 40").unwrap(), None);
+            assert_eq!(session.eval("a + 2").unwrap(), Some(Value::Int(42)));
+            assert_eq!(session.eval("b = a == 40\nc = 1").unwrap(), None);
+            assert_eq!(session.eval("b").unwrap(), Some(Value::Bool(true)));
+            assert_eq!(session.eval("if b then\n  c = c + 1").unwrap(), None);
+            assert_eq!(session.variable("c"), Some(Value::Int(2)));
+            assert_eq!(session.variable("d"), None);
+            assert_eq!(session.eval("").unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn eval_error_test() {
+        for mut session in sessions() {
+            session.eval("a = 1").unwrap();
+            for source in ["a +", "a = = 1", "a = 1\nb = = 2", "1 2", "`"] {
+                assert!(
+                    matches!(session.eval(source), Err(Error::Parse(_))),
+                    "{source}"
+                );
+            }
+            for source in ["b", "(a == 1) + 1", "a = a == 1", "if a then print a"] {
+                assert!(
+                    matches!(session.eval(source), Err(Error::Type(_))),
+                    "{source}"
+                );
+            }
+            // 型エラーのあるプログラムは、一つの文も実行しない
+            assert!(session.eval("a = 2\nc = a == 1\nd = c + 1").is_err());
+            assert_eq!(session.variable("a"), Some(Value::Int(1)));
+            assert_eq!(session.variable("c"), None);
+            assert_eq!(session.eval("a + 1").unwrap(), Some(Value::Int(2)));
+        }
+    }
+
+    #[test]
+    fn error_position_test() {
+        let mut session = Session::with_interpreter();
+        let Err(Error::Parse(err)) = session.eval("a = 1\nb = = 2") else {
+            panic!("expected a parse error");
+        };
+        assert_eq!(err.pos.line, 2);
+    }
+
+    extern "C" fn double(n: u32) -> u32 {
+        n.wrapping_mul(2)
+    }
+
+    extern "C" fn is_even(n: u32) -> u32 {
+        n.is_multiple_of(2) as u32
+    }
+
+    fn host_functions() -> [HostFunction; 2] {
+        [
+            HostFunction::new(
+                "double",
+                &[TypeRepr::Int],
+                TypeRepr::Int,
+                HostFn::Arity1(double),
+            )
+            .unwrap(),
+            HostFunction::new(
+                "isEven",
+                &[TypeRepr::Int],
+                TypeRepr::Bool,
+                HostFn::Arity1(is_even),
+            )
+            .unwrap(),
+        ]
+    }
+
+    #[test]
+    fn add_function_test() {
+        for mut session in sessions() {
+            session.eval("a = 21").unwrap();
+            assert!(matches!(session.eval("double(a)"), Err(Error::Type(_))));
+            for func in host_functions() {
+                session.add_function(func).unwrap();
+            }
+            // 関数を追加する前の変数の値は引き継がれる
+            assert_eq!(session.eval("double(a)").unwrap(), Some(Value::Int(42)));
+            session
+                .eval("b = isEven(double(a) + 1)\nif isEven(a + 1) then a = double(double(a))")
+                .unwrap();
+            assert_eq!(session.variable("a"), Some(Value::Int(84)));
+            assert_eq!(session.variable("b"), Some(Value::Bool(false)));
+            for source in ["double(b)", "double(1, 2)", "isEven(1) + 1", "triple(1)"] {
+                assert!(
+                    matches!(session.eval(source), Err(Error::Type(_))),
+                    "{source}"
+                );
+            }
+
+            let snapshot = session.snapshot();
+            session.reset().unwrap();
+            assert_eq!(session.eval("isEven(2)").unwrap(), Some(Value::Bool(true)));
+            session.rollback(snapshot);
+            assert_eq!(session.eval("double(a)").unwrap(), Some(Value::Int(168)));
+        }
+    }
+
+    #[test]
+    fn jit_builder_function_test() {
+        let [func, _] = host_functions();
+        let mut session = Session::with_jit(JITContext::builder().function(func)).unwrap();
+        assert_eq!(session.eval("double(2)").unwrap(), Some(Value::Int(4)));
+    }
+
+    extern "C" fn identity(n: u32) -> u32 {
+        n
+    }
+
+    #[test]
+    fn normalize_bool_return_test() {
+        for mut session in sessions() {
+            // 真偽値を返すと宣言しているが、0 か 1 以外も返す
+            let non_zero = HostFunction::new(
+                "nonZero",
+                &[TypeRepr::Int],
+                TypeRepr::Bool,
+                HostFn::Arity1(identity),
+            )
+            .unwrap();
+            let to_int = HostFunction::new(
+                "toInt",
+                &[TypeRepr::Bool],
+                TypeRepr::Int,
+                HostFn::Arity1(identity),
+            )
+            .unwrap();
+            session.add_function(non_zero).unwrap();
+            session.add_function(to_int).unwrap();
+            session.eval("b = nonZero(2)").unwrap();
+            // モジュールを作り直した後も正規化する
+            for _ in 0..300 {
+                assert_eq!(
+                    session.eval("toInt(nonZero(2))").unwrap(),
+                    Some(Value::Int(1))
+                );
+            }
+            assert_eq!(session.eval("toInt(b)").unwrap(), Some(Value::Int(1)));
+        }
+    }
+
+    thread_local! {
+        static CALLS: Cell<u32> = const { Cell::new(0) };
+    }
+
+    extern "C" fn count(n: u32) -> u32 {
+        CALLS.with(|calls| calls.set(calls.get() + n));
+        CALLS.with(Cell::get)
+    }
+
+    #[test]
+    fn call_in_dead_if_test() {
+        for mut session in sessions() {
+            let func = HostFunction::new(
+                "count",
+                &[TypeRepr::Int],
+                TypeRepr::Int,
+                HostFn::Arity1(count),
+            )
+            .unwrap();
+            session.add_function(func).unwrap();
+            CALLS.with(|calls| calls.set(0));
+            // 本体は実行されないが、条件式の関数呼び出しは実行される
+            session
+                .eval("if count(2) == 0 then if 1 == 0 then print 1")
+                .unwrap();
+            assert_eq!(session.take_warnings().len(), 1);
+            assert_eq!(CALLS.with(Cell::get), 2);
+        }
+    }
+
+    #[test]
+    fn deeply_nested_input_test() {
+        let n = 100_000;
+        for source in [
+            format!("{}1{}", "(".repeat(n), ")".repeat(n)),
+            format!("a = 1{}", " + 1".repeat(n)),
+            format!("{}print 1", "if 1 == 1 then ".repeat(n)),
+        ] {
+            for mut session in sessions() {
+                assert!(matches!(session.eval(&source), Err(Error::Parse(_))));
+                assert_eq!(session.eval("1 + 1").unwrap(), Some(Value::Int(2)));
+            }
+        }
+    }
+
+    #[test]
+    fn snapshot_test() {
+        for mut session in sessions() {
+            session.eval("a = 1").unwrap();
+            let snapshot = session.snapshot();
+            session.eval("a = a + 1\nb = a == 2").unwrap();
+            session.rollback(snapshot);
+            assert_eq!(session.variable("a"), Some(Value::Int(1)));
+            assert!(session.eval("b").is_err());
+            assert_eq!(session.variable_names(), vec!["a".to_owned()]);
+        }
+    }
+
+    #[test]
+    fn unassigned_variable_test() {
+        for mut session in sessions() {
+            session.eval("b = 1 == 2\nif b then c = 1").unwrap();
+            assert_eq!(session.variable("c"), None);
+            let snapshot = session.snapshot();
+            session.eval("c = 2").unwrap();
+            assert_eq!(session.variable("c"), Some(Value::Int(2)));
+            // 巻き戻すと代入前の値のない状態に戻る
+            session.rollback(snapshot);
+            assert_eq!(session.variable("c"), None);
+            let vars = session.variables();
+            assert_eq!(vars[1].value, None);
+        }
+    }
+
+    #[test]
+    fn keep_emit_options_test() {
+        let mut session = Session::new().unwrap();
+        session.emit_options().unwrap().asm = true;
+        session.reset().unwrap();
+        assert!(session.emit_options().unwrap().asm);
+        let [func, _] = host_functions();
+        session.add_function(func).unwrap();
+        assert!(session.emit_options().unwrap().asm);
+    }
+
+    #[test]
+    fn restore_variables_test() {
+        for mut session in sessions() {
+            session.eval("a = 1\nb = a == 1\nif b then c = 3").unwrap();
+            let vars = session.variables();
+            let names: Vec<&str> = vars.iter().map(|var| var.name.as_str()).collect();
+            assert_eq!(names, vec!["a", "b", "c"]);
+
+            session.reset().unwrap();
+            assert_eq!(session.variables(), vec![]);
+            assert!(session.eval("a").is_err());
+
+            // ファイルに書き出して読み戻しても同じ変数になる
+            let vars = session_file::read_session(&session_file::write_session(&vars)).unwrap();
+            session.restore_variables(&vars).unwrap();
+            assert_eq!(session.variables(), vars);
+            assert_eq!(session.eval("a + 1").unwrap(), Some(Value::Int(2)));
+            assert!(session.eval("c").is_err());
+        }
+    }
+
+    #[test]
+    fn restore_invalid_variables_test() {
+        for mut session in sessions() {
+            session.eval("a = 1").unwrap();
+            for var in [
+                Variable {
+                    name: "d".to_owned(),
+                    ty: TypeRepr::Int,
+                    assigned: true,
+                    value: None,
+                },
+                Variable {
+                    name: "d".to_owned(),
+                    ty: TypeRepr::Int,
+                    assigned: true,
+                    value: Some(Value::Bool(true)),
+                },
+            ] {
+                assert!(matches!(
+                    session.restore_variables(&[var]),
+                    Err(Error::InvalidVariable(_))
+                ));
+                // 失敗した場合はセッションをそのまま残す
+                assert_eq!(session.variable("a"), Some(Value::Int(1)));
+            }
+        }
+    }
+}
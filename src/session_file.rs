@@ -0,0 +1,175 @@
+use crate::{interp::Value, lexer, type_::TypeRepr};
+use std::fmt::{self, Display, Formatter};
+
+// `:save` で書き出すセッションファイルの一行。変数の型と値を表す
+//
+//     a : int = 40       代入済みの変数
+//     b : bool ?= true   `if` の本体でしか代入されていない変数
+//     c : int ?=         値のない変数（`if` の本体が一度も実行されていない場合など）
+// 代入済みの変数には必ず値があるため、値のない変数は代入済みでも `?=` で書く
+// `c : int` のように `=` のない行は `c : int ?=` と同じ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variable {
+    pub name: String,
+    pub ty: TypeRepr,
+    // 代入済みであることが確定しているか
+    pub assigned: bool,
+    pub value: Option<Value>,
+}
+
+impl Display for Variable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} : {}", self.name, self.ty)?;
+        match (self.value, self.assigned) {
+            (Some(value), true) => write!(f, " = {value}"),
+            (Some(value), false) => write!(f, " ?= {value}"),
+            (None, _) => write!(f, " ?="),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub line: usize,
+    pub text: String,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Session file error at line {}: invalid entry `{}`",
+            self.line, self.text
+        )
+    }
+}
+
+pub fn write_session(vars: &[Variable]) -> String {
+    let mut out = String::from("# simplelang session\n");
+    for var in vars {
+        out.push_str(&format!("{var}\n"));
+    }
+    out
+}
+
+// 空行と `#` で始まる行は読み飛ばす
+pub fn read_session(s: &str) -> Result<Vec<Variable>, Error> {
+    s.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(index, line)| {
+            parse_variable(line.trim()).ok_or_else(|| Error {
+                line: index + 1,
+                text: line.trim().to_owned(),
+            })
+        })
+        .collect()
+}
+
+fn parse_variable(line: &str) -> Option<Variable> {
+    let (name, rest) = line.split_once(':')?;
+    let name = name.trim();
+    // 識別子として字句解析できる名前だけを受け付ける
+    let tokens = lexer::tokenize(name).ok()?;
+    if !matches!(tokens.as_slice(), [token, _] if token.kind == lexer::TokenKind::Identifier(name.to_owned()))
+    {
+        return None;
+    }
+
+    let (ty, value, assigned) = match rest.split_once('=') {
+        Some((ty, value)) => match ty.strip_suffix('?') {
+            Some(ty) => (ty, Some(value.trim()), false),
+            None => (ty, Some(value.trim()), true),
+        },
+        None => (rest, None, false),
+    };
+    let ty = match ty.trim() {
+        "int" => TypeRepr::Int,
+        "bool" => TypeRepr::Bool,
+        _ => return None,
+    };
+    let value = match (ty, value) {
+        // 代入済みの変数には値が必要
+        (_, Some("")) if assigned => return None,
+        (_, None | Some("")) => None,
+        (TypeRepr::Int, Some(value)) => Some(Value::Int(value.parse().ok()?)),
+        (_, Some(value)) => Some(Value::Bool(value.parse().ok()?)),
+    };
+    Some(Variable {
+        name: name.to_owned(),
+        ty,
+        assigned,
+        value,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_session, write_session, Variable};
+    use crate::{interp::Value, type_::TypeRepr};
+
+    #[test]
+    fn write_and_read_session_test() {
+        let vars = vec![
+            Variable {
+                name: "a".to_owned(),
+                ty: TypeRepr::Int,
+                assigned: true,
+                value: Some(Value::Int(u32::MAX)),
+            },
+            Variable {
+                name: "b".to_owned(),
+                ty: TypeRepr::Bool,
+                assigned: false,
+                value: Some(Value::Bool(true)),
+            },
+            Variable {
+                name: "c".to_owned(),
+                ty: TypeRepr::Int,
+                assigned: false,
+                value: None,
+            },
+        ];
+        let text = write_session(&vars);
+        assert_eq!(
+            text,
+            "# simplelang session\na : int = 4294967295\nb : bool ?= true\nc : int ?=\n"
+        );
+        assert_eq!(read_session(&text).unwrap(), vars);
+        assert_eq!(read_session("c : int").unwrap(), vars[2..3]);
+    }
+
+    #[test]
+    fn write_assigned_variable_without_value_test() {
+        // 値のない変数は、代入済みとしては読み戻さない
+        let var = Variable {
+            name: "d".to_owned(),
+            ty: TypeRepr::Int,
+            assigned: true,
+            value: None,
+        };
+        let text = write_session(std::slice::from_ref(&var));
+        assert_eq!(text, "# simplelang session\nd : int ?=\n");
+        let expected = Variable {
+            assigned: false,
+            ..var
+        };
+        assert_eq!(read_session(&text).unwrap(), vec![expected]);
+    }
+
+    #[test]
+    fn read_session_error_test() {
+        for line in [
+            "a : int = true",
+            "a : float = 1",
+            "if : int = 1",
+            "a b : int",
+            "a = 1",
+            "a : int =",
+        ] {
+            let err = read_session(&format!("\n{line}\n")).unwrap_err();
+            assert_eq!(err.line, 2, "{line}");
+            assert_eq!(err.text, line);
+        }
+    }
+}
@@ -1,8 +1,23 @@
 use super::expr::*;
+use std::fmt::{self, Display, Formatter};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Statement {
     Assign(Identifier, Expression),
     If(Expression, Box<Statement>),
     Print(Expression),
+    // 値を捨てて式を評価する。構文にはなく、最適化で本体の消えた `if` の条件式に残る
+    // 関数呼び出しを実行するために使う
+    Eval(Expression),
+}
+
+impl Display for Statement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Assign(ident, expr) => write!(f, "{ident} = {expr}"),
+            Self::If(cond, body_stmt) => write!(f, "if {cond} then {body_stmt}"),
+            Self::Print(expr) => write!(f, "print {expr}"),
+            Self::Eval(expr) => write!(f, "{expr}"),
+        }
+    }
 }
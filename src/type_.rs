@@ -1,3 +1,5 @@
+use std::fmt::{self, Display, Formatter};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TypeRepr {
     Int,
@@ -5,6 +7,17 @@ pub enum TypeRepr {
     Id(usize),
 }
 
+impl Display for TypeRepr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int => write!(f, "int"),
+            Self::Bool => write!(f, "bool"),
+            // 型変数は解決してから表示するため、ここに来るのは型が決まっていない場合のみ
+            Self::Id(_) => write!(f, "unknown"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Type {
     Int,
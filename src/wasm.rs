@@ -0,0 +1,241 @@
+use crate::{expr::*, stmt::*};
+use std::collections::HashMap;
+use wasm_encoder::{
+    BlockType, CodeSection, ConstExpr, EntityType, ExportKind, ExportSection, Function,
+    FunctionSection, GlobalSection, GlobalType, ImportSection, Instruction, Module, TypeSection,
+    ValType,
+};
+
+// 型のインデックス。ホストの関数の型はこの後に並ぶ
+const PRINT_TYPE: u32 = 0;
+const MAIN_TYPE: u32 = 1;
+// 関数のインデックス。インポートした関数が先に並び、ホストの関数は `print` の後に続く
+const PRINT_FUNC: u32 = 0;
+
+// 型検査を終えた文の列を WebAssembly モジュールにコンパイルする
+//
+// - 数値を出力する関数 `env.print: (i32) -> ()` をインポートする
+// - ホストの関数 `f` の呼び出しごとに、`env.f: (i32, ...) -> i32` をインポートする
+// - 変数はそれぞれ可変な i32 のグローバル変数になり、`var.{name}` としてエクスポートされる
+// - すべての文を順に実行する関数を `main: () -> ()` としてエクスポートする
+pub fn compile_program(stmts: &[Statement]) -> Vec<u8> {
+    let mut codegen = WasmCodegen {
+        globals: HashMap::new(),
+        global_names: Vec::new(),
+        host_funcs: HashMap::new(),
+        host_imports: Vec::new(),
+        func: Function::new([]),
+    };
+    for stmt in stmts {
+        codegen.codegen_stmt(stmt);
+    }
+    codegen.func.instruction(&Instruction::End);
+    codegen.finish()
+}
+
+struct WasmCodegen {
+    globals: HashMap<String, u32>,
+    // インデックス順の変数名
+    global_names: Vec<String>,
+    // ホストの関数の名前と関数のインデックス
+    host_funcs: HashMap<String, u32>,
+    // インデックス順の、ホストの関数の名前と引数の数
+    host_imports: Vec<(String, usize)>,
+    func: Function,
+}
+
+impl WasmCodegen {
+    fn finish(self) -> Vec<u8> {
+        let mut types = TypeSection::new();
+        types.function([ValType::I32], []);
+        types.function([], []);
+
+        let mut imports = ImportSection::new();
+        imports.import("env", "print", EntityType::Function(PRINT_TYPE));
+        for (index, (name, arity)) in self.host_imports.iter().enumerate() {
+            types.function(vec![ValType::I32; *arity], [ValType::I32]);
+            let type_index = MAIN_TYPE + 1 + index as u32;
+            imports.import("env", name, EntityType::Function(type_index));
+        }
+
+        let mut functions = FunctionSection::new();
+        functions.function(MAIN_TYPE);
+
+        let mut globals = GlobalSection::new();
+        let mut exports = ExportSection::new();
+        let main_func = PRINT_FUNC + 1 + self.host_imports.len() as u32;
+        exports.export("main", ExportKind::Func, main_func);
+        for (index, name) in self.global_names.iter().enumerate() {
+            let global_type = GlobalType {
+                val_type: ValType::I32,
+                mutable: true,
+            };
+            globals.global(global_type, &ConstExpr::i32_const(0));
+            // 識別子に含まれない `.` を付けて、`main` との衝突を避ける
+            exports.export(&format!("var.{name}"), ExportKind::Global, index as u32);
+        }
+
+        let mut code = CodeSection::new();
+        code.function(&self.func);
+
+        let mut module = Module::new();
+        module
+            .section(&types)
+            .section(&imports)
+            .section(&functions)
+            .section(&globals)
+            .section(&exports)
+            .section(&code);
+        module.finish()
+    }
+
+    fn codegen_stmt(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::Print(expr) => {
+                self.codegen_expr(expr);
+                self.func.instruction(&Instruction::Call(PRINT_FUNC));
+            }
+            Statement::Assign(ident, expr) => {
+                self.codegen_expr(expr);
+                let index = self.global_index(ident);
+                self.func.instruction(&Instruction::GlobalSet(index));
+            }
+            Statement::If(cond, then) => {
+                self.codegen_expr(cond);
+                self.func.instruction(&Instruction::If(BlockType::Empty));
+                self.codegen_stmt(then);
+                self.func.instruction(&Instruction::End);
+            }
+            Statement::Eval(expr) => {
+                self.codegen_expr(expr);
+                self.func.instruction(&Instruction::Drop);
+            }
+        }
+    }
+
+    // 真偽値も i32 の 0 か 1 として表す
+    fn codegen_expr(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Number(num) => {
+                let num: u32 = num.into();
+                self.func.instruction(&Instruction::I32Const(num as i32));
+            }
+            Expression::Add(lhs, rhs) => self.codegen_binary(lhs, rhs, Instruction::I32Add),
+            Expression::Sub(lhs, rhs) => self.codegen_binary(lhs, rhs, Instruction::I32Sub),
+            Expression::Comp(lhs, rhs) => self.codegen_binary(lhs, rhs, Instruction::I32Eq),
+            Expression::Identifier(ident) => {
+                let index = self.global_index(ident);
+                self.func.instruction(&Instruction::GlobalGet(index));
+            }
+            Expression::Call(func_name, args) => {
+                for arg in args {
+                    self.codegen_expr(arg);
+                }
+                let index = self.host_func_index(func_name, args.len());
+                self.func.instruction(&Instruction::Call(index));
+            }
+        }
+    }
+
+    // 初めて呼び出したホストの関数は、新しくインポートする
+    fn host_func_index(&mut self, func_name: &Identifier, arity: usize) -> u32 {
+        let func_name: &str = func_name;
+        if let Some(index) = self.host_funcs.get(func_name) {
+            return *index;
+        }
+        let index = PRINT_FUNC + 1 + self.host_imports.len() as u32;
+        self.host_funcs.insert(func_name.to_owned(), index);
+        self.host_imports.push((func_name.to_owned(), arity));
+        index
+    }
+
+    fn codegen_binary(&mut self, lhs: &Expression, rhs: &Expression, op: Instruction) {
+        self.codegen_expr(lhs);
+        self.codegen_expr(rhs);
+        self.func.instruction(&op);
+    }
+
+    // 初めて現れた変数には新しいグローバル変数を割り当てる
+    fn global_index(&mut self, ident: &Identifier) -> u32 {
+        let ident: &str = ident;
+        if let Some(index) = self.globals.get(ident) {
+            return *index;
+        }
+        let index = self.global_names.len() as u32;
+        self.globals.insert(ident.to_owned(), index);
+        self.global_names.push(ident.to_owned());
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compile_program;
+    use crate::{
+        parser::{parse_expr, parse_program},
+        stmt::Statement,
+    };
+    use wasmparser::{Payload, TypeRef, Validator};
+
+    #[test]
+    fn compile_program_test() {
+        let stmts = parse_program("a = 40\nb = a + 2\nc = b == 42\nif c then print b - 1\nprint a")
+            .unwrap();
+        let wasm = compile_program(&stmts);
+        Validator::new().validate_all(&wasm).unwrap();
+
+        let mut imports = Vec::new();
+        let mut exports = Vec::new();
+        for payload in wasmparser::Parser::new(0).parse_all(&wasm) {
+            match payload.unwrap() {
+                Payload::ImportSection(reader) => {
+                    for import in reader {
+                        let import = import.unwrap();
+                        assert!(matches!(import.ty, TypeRef::Func(0)));
+                        imports.push(format!("{}.{}", import.module, import.name));
+                    }
+                }
+                Payload::ExportSection(reader) => {
+                    for export in reader {
+                        exports.push(export.unwrap().name.to_owned());
+                    }
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(imports, vec!["env.print"]);
+        assert_eq!(exports, vec!["main", "var.a", "var.b", "var.c"]);
+    }
+
+    #[test]
+    fn compile_call_test() {
+        let stmts = parse_program("a = f(1, 2)\nprint g(a) + f(a, 3)").unwrap();
+        let wasm = compile_program(&stmts);
+        Validator::new().validate_all(&wasm).unwrap();
+
+        let mut imports = Vec::new();
+        for payload in wasmparser::Parser::new(0).parse_all(&wasm) {
+            if let Payload::ImportSection(reader) = payload.unwrap() {
+                for import in reader {
+                    let import = import.unwrap();
+                    imports.push(format!("{}.{}", import.module, import.name));
+                }
+            }
+        }
+        assert_eq!(imports, vec!["env.print", "env.f", "env.g"]);
+    }
+
+    #[test]
+    fn compile_eval_test() {
+        let mut stmts = parse_program("a = 1").unwrap();
+        stmts.push(Statement::Eval(parse_expr("f(a) == 1").unwrap()));
+        let wasm = compile_program(&stmts);
+        Validator::new().validate_all(&wasm).unwrap();
+    }
+
+    #[test]
+    fn compile_empty_program_test() {
+        let wasm = compile_program(&[]);
+        Validator::new().validate_all(&wasm).unwrap();
+    }
+}